@@ -0,0 +1,54 @@
+use std::fs::{self, File};
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use serde_json::json;
+
+use crate::clock;
+
+// ============================================================================
+// Run Capture
+// ============================================================================
+
+/// Open capture file, set once by `enable`.
+static CAPTURE: OnceLock<Mutex<LineWriter<File>>> = OnceLock::new();
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Starts recording every stdin and stdout line to `<dir>/<pid>.jsonl`, one
+/// `{"in": line, "ts_us": t}` or `{"out": line, "ts_us": t}` object per line
+/// in the order the node saw them, `t` counting from when capture started.
+/// Maelstrom runs each node as its own process, so the pid keeps their
+/// captures apart.
+pub fn enable(dir: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}.jsonl", std::process::id()));
+    let file = LineWriter::new(File::create(path)?);
+    START.get_or_init(clock::now);
+    CAPTURE
+        .set(Mutex::new(file))
+        .map_err(|_| anyhow::anyhow!("capture already enabled"))
+}
+
+/// Records a line read from stdin.
+pub fn record_in(line: &str) {
+    record("in", line);
+}
+
+/// Records a line written to stdout.
+pub fn record_out(line: &str) {
+    record("out", line);
+}
+
+fn record(direction: &str, line: &str) {
+    let Some(file) = CAPTURE.get() else {
+        return;
+    };
+    // Capture is best effort; a failed write must not take the node down.
+    let mut file = file.lock().expect("capture file poisoned");
+    let ts_us = START
+        .get()
+        .map_or(0, |start| clock::elapsed(*start).as_micros() as u64);
+    let _ = writeln!(file, "{}", json!({ direction: line, "ts_us": ts_us }));
+}
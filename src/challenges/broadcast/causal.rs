@@ -0,0 +1,175 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::VectorClock;
+
+// ============================================================================
+// Causal Delivery
+// ============================================================================
+
+/// Who broadcast a value and which broadcasts it had already delivered.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CausalStamp {
+    pub origin: String,
+    pub clock: VectorClock,
+}
+
+/// Holds back values until every broadcast that causally precedes them has
+/// been delivered, so reads in `--causal` mode never see an effect without
+/// its cause.
+#[derive(Debug, Default)]
+pub struct CausalState {
+    /// Broadcasts delivered from each origin.
+    delivered: VectorClock,
+    stamps: HashMap<u64, CausalStamp>,
+    /// Received values still waiting for a causal predecessor.
+    pending: HashSet<u64>,
+    visible: HashSet<u64>,
+}
+
+impl CausalState {
+    /// Stamps a value broadcast on `node_id` after everything it has
+    /// delivered so far, and delivers it.
+    pub fn broadcast(&mut self, node_id: &str, value: u64) {
+        if self.stamps.contains_key(&value) {
+            return;
+        }
+        let mut clock = self.delivered.clone();
+        clock.increment(node_id);
+        let stamp = CausalStamp {
+            origin: node_id.to_string(),
+            clock,
+        };
+        self.receive(&[(value, stamp)]);
+    }
+
+    /// Takes in stamped values from a peer and delivers whatever is ready.
+    pub fn receive(&mut self, stamped: &[(u64, CausalStamp)]) {
+        for (value, stamp) in stamped {
+            if !self.stamps.contains_key(value) {
+                self.stamps.insert(*value, stamp.clone());
+                self.pending.insert(*value);
+            }
+        }
+        self.deliver_ready();
+    }
+
+    /// Stamps of the given values, to send along with them.
+    pub fn stamps_for(&self, values: &HashSet<u64>) -> Vec<(u64, CausalStamp)> {
+        values
+            .iter()
+            .filter_map(|value| Some((*value, self.stamps.get(value)?.clone())))
+            .collect()
+    }
+
+    pub fn visible(&self) -> HashSet<u64> {
+        self.visible.clone()
+    }
+
+    fn deliver_ready(&mut self) {
+        loop {
+            let ready: Vec<u64> = self
+                .pending
+                .iter()
+                .filter(|value| self.is_deliverable(&self.stamps[value]))
+                .copied()
+                .collect();
+            if ready.is_empty() {
+                return;
+            }
+            for value in ready {
+                let stamp = &self.stamps[&value];
+                self.delivered
+                    .observe(&stamp.origin, stamp.clock.get(&stamp.origin));
+                self.pending.remove(&value);
+                self.visible.insert(value);
+            }
+        }
+    }
+
+    /// The next broadcast from its origin, with every other dependency
+    /// already delivered.
+    fn is_deliverable(&self, stamp: &CausalStamp) -> bool {
+        stamp.clock.get(&stamp.origin) == self.delivered.get(&stamp.origin) + 1
+            && stamp.clock.iter().all(|(node, counter)| {
+                *node == stamp.origin || *counter <= self.delivered.get(node)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /// Applies random broadcasts and out-of-order gossip of random subsets
+    /// between three nodes. After every step the visible values only grow
+    /// and each one's causal predecessors are visible too. Once every node
+    /// has received every stamp, they all see the same values.
+    #[test]
+    fn random_operations_deliver_in_causal_order() {
+        let nodes = ["n1", "n2", "n3"];
+        for seed in 0..200 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut states: [CausalState; 3] = Default::default();
+            let mut value = 0;
+            for step in 0..100 {
+                let i = rng.random_range(0..3);
+                let before = states[i].visible();
+                if rng.random_bool(0.4) {
+                    value += 1;
+                    states[i].broadcast(nodes[i], value);
+                } else {
+                    let from = rng.random_range(0..3);
+                    let values = states[from]
+                        .stamps
+                        .keys()
+                        .copied()
+                        .filter(|_| rng.random_bool(0.5))
+                        .collect();
+                    let stamped = states[from].stamps_for(&values);
+                    states[i].receive(&stamped);
+                }
+
+                let state = &states[i];
+                assert!(before.is_subset(&state.visible), "seed {seed} step {step}");
+                for visible in &state.visible {
+                    let stamp = &state.stamps[visible];
+                    let delivered = state.delivered.compare(&stamp.clock);
+                    assert!(
+                        matches!(delivered, Some(Ordering::Greater | Ordering::Equal)),
+                        "seed {seed} step {step}: {visible} delivered before its causes"
+                    );
+                }
+                for node in nodes {
+                    let from_node = state
+                        .visible
+                        .iter()
+                        .filter(|value| state.stamps[value].origin == node);
+                    assert_eq!(
+                        from_node.count() as u64,
+                        state.delivered.get(node),
+                        "seed {seed} step {step}: {node}"
+                    );
+                }
+            }
+
+            for i in 0..3 {
+                for from in 0..3 {
+                    let values = states[from].stamps.keys().copied().collect();
+                    let stamped = states[from].stamps_for(&values);
+                    states[i].receive(&stamped);
+                }
+            }
+            for state in &states {
+                assert!(state.pending.is_empty(), "seed {seed}");
+                assert_eq!(state.visible.len(), value as usize, "seed {seed}");
+            }
+        }
+    }
+}
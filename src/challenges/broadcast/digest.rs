@@ -0,0 +1,221 @@
+use std::collections::{BTreeSet, HashSet};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::challenges::broadcast::causal::CausalStamp;
+use crate::challenges::broadcast::gossip::send_gossip;
+use crate::challenges::broadcast::{BroadcastData, ensure_gossip_task, gossip_missing};
+use crate::challenges::cluster::global_cluster;
+use crate::challenges::node::Node;
+use crate::crdt::Tag;
+use crate::output::Output;
+use crate::{BodyBase, Message, Payload, send};
+
+// ============================================================================
+// Digest Anti-Entropy
+// ============================================================================
+//
+// Every `--anti-entropy-rounds` gossip rounds a node sends each peer a digest
+// of its values: a count and checksum for each of `DIGEST_BUCKETS` hash
+// buckets. The peer answers with its values from the buckets whose digests
+// differ, and the node sends back whichever of its own values from those
+// buckets the peer turned out to lack. Peers holding the same values only
+// ever exchange the digests.
+
+/// Hash buckets the value set is split into for a digest.
+pub const DIGEST_BUCKETS: usize = 64;
+
+/// Count and checksum of the values in one bucket.
+pub type BucketDigest = (u64, u64);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DigestBody {
+    #[serde(flatten)]
+    pub base: BodyBase,
+
+    /// The sender's digest, one entry per bucket.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buckets: Option<Vec<BucketDigest>>,
+
+    /// Buckets whose digests differed, in a `digest_diff`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mismatched: Option<BTreeSet<usize>>,
+
+    /// The sender's values from the mismatched buckets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<HashSet<u64>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub causal_stamps: Option<Vec<(u64, CausalStamp)>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequences: Option<Vec<(u64, Tag)>>,
+}
+
+/// Scrambles a value so that neither bucket nor checksum depends on how the
+/// values are spread over the u64 range.
+fn mix(value: u64) -> u64 {
+    let mut x = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+fn bucket_of(value: u64) -> usize {
+    (mix(value) % DIGEST_BUCKETS as u64) as usize
+}
+
+/// The digest of `values`. Checksums are wrapping sums, so they don't depend
+/// on iteration order.
+pub fn digest(values: &HashSet<u64>) -> Vec<BucketDigest> {
+    let mut buckets: Vec<BucketDigest> = vec![(0, 0); DIGEST_BUCKETS];
+    for value in values {
+        let (count, checksum) = &mut buckets[bucket_of(*value)];
+        *count += 1;
+        *checksum = checksum.wrapping_add(mix(*value));
+    }
+    buckets
+}
+
+/// Digest messages for one gossip round, one per peer on every
+/// `--anti-entropy-rounds`th round and none otherwise.
+pub fn digest_messages(node_id: &str, round: u64) -> Vec<Message<Payload>> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let every = cluster.config.anti_entropy_rounds;
+    if every == 0 || round % every != every - 1 {
+        return Vec::new();
+    }
+    let Some(node) = cluster.get_node(node_id) else {
+        return Vec::new();
+    };
+    let broadcast_data = node.broadcast_data.lock();
+    let Some(broadcast_data) = broadcast_data.as_ref() else {
+        return Vec::new();
+    };
+
+    let buckets = digest(&broadcast_data.clone_data());
+    node.peers
+        .iter()
+        .filter(|peer| **peer != node.id)
+        .map(|peer| Message {
+            src: node.id.clone(),
+            dest: peer.clone(),
+            body: Payload::Digest(DigestBody {
+                buckets: Some(buckets.clone()),
+                ..Default::default()
+            }),
+        })
+        .collect()
+}
+
+/// Compares a peer's digest with ours and sends back our values from the
+/// buckets that differ. Digest messages are one-way and carry no msg_id.
+pub async fn digest_request(msg: Message<DigestBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+    let Some(theirs) = &msg.body.buckets else {
+        return Ok(());
+    };
+
+    let response = {
+        let mut broadcast_data = node.broadcast_data.lock();
+        let broadcast_data = broadcast_data.get_or_insert_with(BroadcastData::new);
+        let values = broadcast_data.clone_data();
+        let mismatched: BTreeSet<usize> = digest(&values)
+            .iter()
+            .zip(theirs)
+            .enumerate()
+            .filter(|(_, (ours, theirs))| ours != theirs)
+            .map(|(bucket, _)| bucket)
+            .collect();
+        let (differing, matching) = split_by_bucket(values, &mismatched);
+        broadcast_data.record_ack(&msg.src, &matching);
+        if mismatched.is_empty() {
+            return Ok(());
+        }
+
+        let causal_stamps = broadcast_data.causal.stamps_for(&differing);
+        let sequences = broadcast_data.sequence.tags_for(&differing);
+        Message {
+            src: node.id.clone(),
+            dest: msg.src.clone(),
+            body: Payload::DigestDiff(DigestBody {
+                base: BodyBase::default(),
+                buckets: None,
+                mismatched: Some(mismatched),
+                values: Some(differing),
+                causal_stamps: (!causal_stamps.is_empty()).then_some(causal_stamps),
+                sequences: (!sequences.is_empty()).then_some(sequences),
+            }),
+        }
+    };
+    send(&response, output)
+}
+
+/// Merges a peer's values from the buckets where our digests differed, then
+/// gossips it the values from those buckets it lacks.
+pub async fn digest_diff(msg: Message<DigestBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+    let mismatched = msg.body.mismatched.clone().unwrap_or_default();
+    let theirs = msg.body.values.clone().unwrap_or_default();
+    merge(&msg, node)?;
+    ensure_gossip_task(node, &cluster, output);
+
+    let gossip = {
+        let broadcast_data = node.broadcast_data.lock();
+        let Some(broadcast_data) = broadcast_data.as_ref() else {
+            return Ok(());
+        };
+        let (differing, matching) = split_by_bucket(broadcast_data.clone_data(), &mismatched);
+        broadcast_data.record_ack(&msg.src, &matching);
+        let missing: HashSet<u64> = differing.difference(&theirs).copied().collect();
+        gossip_missing(node, broadcast_data, &msg.src, missing)
+    };
+    if let Some(gossip) = gossip {
+        send_gossip(gossip, output);
+    }
+    Ok(())
+}
+
+/// Merges the values in a `digest_diff` and remembers that the sender holds
+/// them.
+fn merge(msg: &Message<DigestBody>, node: &Node) -> Result<()> {
+    let mut broadcast_data = node.broadcast_data.lock();
+    let broadcast_data = broadcast_data.get_or_insert_with(BroadcastData::new);
+    if let Some(values) = &msg.body.values {
+        let new: Vec<u64> = values
+            .iter()
+            .filter(|value| !broadcast_data.contains(**value))
+            .copied()
+            .collect();
+        if !new.is_empty() {
+            node.log(&new)?;
+        }
+        node.gossip_pacer.record_pending(new.len());
+        broadcast_data.record_ack(&msg.src, values);
+        broadcast_data.extend(values.clone());
+    }
+    if let Some(stamps) = &msg.body.causal_stamps {
+        broadcast_data.causal.receive(stamps);
+    }
+    if let Some(tags) = &msg.body.sequences {
+        broadcast_data.sequence.receive(tags);
+    }
+    Ok(())
+}
+
+/// Splits `values` into those in the `mismatched` buckets and the rest.
+fn split_by_bucket(
+    values: HashSet<u64>,
+    mismatched: &BTreeSet<usize>,
+) -> (HashSet<u64>, HashSet<u64>) {
+    values
+        .into_iter()
+        .partition(|value| mismatched.contains(&bucket_of(*value)))
+}
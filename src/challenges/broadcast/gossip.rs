@@ -1,13 +1,16 @@
-use crate::challenges::cluster::global_cluster;
-use crate::send;
-use crate::BodyBase;
-use crate::challenges::broadcast::spawn_gossip_thread;
-use anyhow::Result;
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::io::Write;
 
-use crate::{Message, challenges::broadcast::BroadcastData};
+use crate::challenges::broadcast::causal::CausalStamp;
+use crate::challenges::broadcast::{BroadcastData, ensure_gossip_task};
+use crate::challenges::cluster::global_cluster;
+use crate::challenges::membership;
+use crate::challenges::node::{MsgIdSpace, Node};
+use crate::crdt::Tag;
+use crate::output::Output;
+use crate::{BodyBase, Message, Payload, clock, metrics, rpc, send};
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GossipBody {
@@ -17,50 +20,280 @@ pub struct GossipBody {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub gossip_data: Option<HashSet<u64>>,
 
+    /// Causal stamps of the gossiped values, in `--causal` mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub causal_stamps: Option<Vec<(u64, CausalStamp)>>,
+
+    /// Sequence tags of the gossiped values, in `--sequence` mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequences: Option<Vec<(u64, Tag)>>,
+
+    /// Set on a `gossip_ok` in `--plumtree` mode when the gossip brought
+    /// nothing new, so the sender stops pushing to us eagerly.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub prune: bool,
+
     pub org_msg_id: u64,
     pub org_msg_src: String,
 }
 
-pub fn gossip(msg: Message<GossipBody>, output: &mut impl Write) -> Result<()> {
-    let mut cluster = global_cluster().write().unwrap();
-    let node = cluster.get_node_mut(&msg.dest).unwrap();
-    if node.broadcast_data.is_none() {
-        node.broadcast_data = Some(BroadcastData::new())
+/// Admin query for the state of gossip to each peer, to diagnose stuck
+/// propagation during a live run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GossipStatusBody {
+    #[serde(flatten)]
+    pub base: BodyBase,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peers: Option<BTreeMap<String, PeerStatus>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerStatus {
+    /// Values sent to the peer and not acknowledged yet.
+    pub unacked: usize,
+    /// Milliseconds since the peer last acknowledged a gossip message.
+    pub last_ack_ms_ago: Option<u64>,
+    pub retransmits: u64,
+    /// Gossip messages still being retransmitted.
+    pub batches_in_flight: usize,
+    /// Smoothed round-trip time of gossip to the peer, in microseconds.
+    pub rtt_us: Option<u64>,
+}
+
+/// Merges gossiped values into the receiving node and remembers that the
+/// sender holds them, so they are never gossiped back to it. Returns how many
+/// of the values were new.
+fn merge_gossip(msg: &Message<GossipBody>, node: &Node) -> Result<usize> {
+    let mut broadcast_data = node.broadcast_data.lock();
+    let broadcast_data = broadcast_data.get_or_insert_with(BroadcastData::new);
+    let mut merged = 0;
+    if let Some(gossip_data) = &msg.body.gossip_data {
+        // The sender will count these as delivered, and never resend them.
+        let new: Vec<u64> = gossip_data
+            .iter()
+            .filter(|value| !broadcast_data.contains(**value))
+            .copied()
+            .collect();
+        if !new.is_empty() {
+            node.log(&new)?;
+        }
+        node.gossip_pacer.record_pending(new.len());
+        merged = new.len();
+        broadcast_data.record_ack(&msg.src, gossip_data);
+        broadcast_data.extend(gossip_data.clone());
     }
-    {
-        let broadcast_data = node.broadcast_data.as_mut().unwrap();
-        broadcast_data.extend(msg.body.gossip_data.unwrap());
+    if let Some(stamps) = &msg.body.causal_stamps {
+        broadcast_data.causal.receive(stamps);
     }
-    if node.gossip_thread.is_none() {
-        let handle = spawn_gossip_thread(node.id.clone());
-        node.gossip_thread = Some(handle.thread().clone());
+    if let Some(tags) = &msg.body.sequences {
+        broadcast_data.sequence.receive(tags);
     }
+    Ok(merged)
+}
 
-    if msg.body.base.typ == "gossip_ok"
-        || !node
-            .broadcast_data
-            .as_mut()
-            .unwrap()
-            .add_if_not_present(&msg.body.org_msg_src, msg.body.org_msg_id)
+pub async fn gossip(msg: Message<GossipBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+    let merged = merge_gossip(&msg, node)?;
+    ensure_gossip_task(node, &cluster, output);
+
+    // Always acknowledge so the sender stops retransmitting, and pull in the
+    // other direction by piggybacking whatever the sender is missing. In
+    // plumtree mode the tree and grafts do the pulling instead, and a gossip
+    // with nothing new prunes the edge it came over.
+    let mut broadcast_data = node.broadcast_data.lock();
+    let prune = cluster.config.plumtree && merged == 0 && msg.body.gossip_data.is_some();
+    if cluster.config.plumtree
+        && let Some(broadcast_data) = broadcast_data.as_mut()
     {
-        return Ok(());
+        if prune {
+            broadcast_data.plumtree.prune(&msg.src);
+        } else if merged > 0 {
+            broadcast_data.plumtree.graft(&msg.src);
+        }
     }
+    let gossip_data = broadcast_data
+        .as_ref()
+        .filter(|_| !cluster.config.plumtree)
+        .map(|broadcast_data| broadcast_data.missing_for(&msg.src))
+        .filter(|missing| !missing.is_empty());
+    let causal_stamps = match (&gossip_data, broadcast_data.as_ref()) {
+        (Some(missing), Some(broadcast_data)) => {
+            Some(broadcast_data.causal.stamps_for(missing)).filter(|stamps| !stamps.is_empty())
+        }
+        _ => None,
+    };
+    let sequences = match (&gossip_data, broadcast_data.as_ref()) {
+        (Some(missing), Some(broadcast_data)) => {
+            Some(broadcast_data.sequence.tags_for(missing)).filter(|tags| !tags.is_empty())
+        }
+        _ => None,
+    };
 
-    let msg_id = node.get_next_id();
-    let response: Message<GossipBody> = Message {
+    let msg_id = node.get_next_id(MsgIdSpace::Gossip);
+    let response: Message<Payload> = Message {
         src: node.id.clone(),
         dest: msg.src,
-        body: GossipBody {
+        body: Payload::GossipOk(GossipBody {
             base: BodyBase {
-                typ: "gossip_ok".to_string(),
                 in_reply_to: msg.body.base.msg_id,
                 msg_id: Some(msg_id),
             },
-            gossip_data: Some(node.broadcast_data.clone().unwrap().data),
+            gossip_data,
+            causal_stamps,
+            sequences,
+            prune,
             org_msg_id: msg.body.org_msg_id,
             org_msg_src: msg.body.org_msg_src.clone(),
-        },
+        }),
+    };
+
+    send(&response, output)
+}
+
+/// Reports the gossip state kept for every peer this node has gossiped with.
+pub async fn gossip_status(msg: Message<GossipStatusBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+
+    let mut peers = BTreeMap::new();
+    if let Some(broadcast_data) = node.broadcast_data.lock().as_ref() {
+        let states = broadcast_data.peers.lock().expect("peer state poisoned");
+        for (peer, state) in states.iter() {
+            let status = PeerStatus {
+                unacked: state.in_flight.len(),
+                last_ack_ms_ago: state.last_ack.map(|at| clock::elapsed(at).as_millis() as u64),
+                retransmits: state.retransmits,
+                batches_in_flight: state.batches_in_flight,
+                rtt_us: rpc::round_trip(peer).map(|rtt| rtt.as_micros() as u64),
+            };
+            peers.insert(peer.clone(), status);
+        }
+    }
+
+    let response = Message {
+        src: node.id.clone(),
+        dest: msg.src,
+        body: Payload::GossipStatusOk(GossipStatusBody {
+            base: BodyBase {
+                msg_id: Some(node.get_next_id(MsgIdSpace::Client)),
+                in_reply_to: msg.body.base.msg_id,
+            },
+            peers: Some(peers),
+        }),
     };
+    send(&response, output)
+}
+
+pub const DEFAULT_GOSSIP_RETRY_MS: u64 = 400;
+pub const DEFAULT_GOSSIP_RETRY_MAX_MS: u64 = 5000;
+
+/// Sends a gossip message as an RPC on its own task (so the dispatch loop can
+/// deliver the reply) and retransmits it with exponential backoff until the
+/// peer's `gossip_ok` arrives or another gossip has delivered the same values.
+pub fn send_gossip(msg: Message<Payload>, output: &Output) {
+    let output = output.clone();
+    tokio::spawn(async move {
+        let values = match &msg.body {
+            Payload::Gossip(body) => body.gossip_data.clone().unwrap_or_default(),
+            _ => HashSet::new(),
+        };
+        let (mut timeout, max_timeout) = {
+            let cluster = global_cluster().read().expect("cluster lock poisoned");
+            (cluster.config.gossip_retry, cluster.config.gossip_retry_max)
+        };
+
+        // Every attempt reuses the original msg_id, so an ack for an earlier
+        // attempt that arrives late still completes the current one.
+        loop {
+            match rpc::call_with_timeout(msg.clone(), timeout, &output).await {
+                Ok(Message {
+                    src,
+                    dest,
+                    body: Payload::GossipOk(body),
+                }) => {
+                    let _ = gossip_ok(Message { src, dest, body }, values);
+                    break;
+                }
+                Err(err) if err.is::<rpc::Timeout>() => {
+                    if is_acked(&msg.src, &msg.dest, &values) {
+                        break;
+                    }
+                    // Stop retransmitting to a peer the failure detector
+                    // declared dead until it is heard from again.
+                    while membership::is_dead(&msg.src, &msg.dest) {
+                        clock::sleep(max_timeout).await;
+                    }
+                    record_retransmit(&msg.src, &msg.dest);
+                    metrics::record_gossip_retry();
+                    timeout = (timeout * 2).min(max_timeout);
+                }
+                // The send failed or the peer answered with an error: no ack
+                // is coming, so hand the values back to the next delta.
+                _ => {
+                    release(&msg.src, &msg.dest, &values);
+                    break;
+                }
+            }
+        }
+        finish_batch(&msg.src, &msg.dest);
+    });
+}
+
+/// Merges the peer's reply and records that it now holds `acked` values.
+fn gossip_ok(msg: Message<GossipBody>, acked: HashSet<u64>) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+    merge_gossip(&msg, node)?;
+    if let Some(broadcast_data) = node.broadcast_data.lock().as_mut() {
+        broadcast_data.record_gossip_ok(&msg.src, &acked);
+        if msg.body.prune {
+            broadcast_data.plumtree.prune(&msg.src);
+        }
+    }
+    Ok(())
+}
+
+fn finish_batch(node_id: &str, peer: &str) {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    if let Some(node) = cluster.get_node(node_id)
+        && let Some(broadcast_data) = node.broadcast_data.lock().as_ref()
+    {
+        broadcast_data.finish_batch(peer);
+    }
+}
+
+fn release(node_id: &str, peer: &str, values: &HashSet<u64>) {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    if let Some(node) = cluster.get_node(node_id)
+        && let Some(broadcast_data) = node.broadcast_data.lock().as_ref()
+    {
+        broadcast_data.release(peer, values);
+    }
+}
+
+fn record_retransmit(node_id: &str, peer: &str) {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    if let Some(node) = cluster.get_node(node_id)
+        && let Some(broadcast_data) = node.broadcast_data.lock().as_ref()
+    {
+        broadcast_data.record_retransmit(peer);
+    }
+}
 
-    return send(&response, output);
+fn is_acked(node_id: &str, peer: &str, values: &HashSet<u64>) -> bool {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    cluster.get_node(node_id).is_some_and(|node| {
+        node.broadcast_data
+            .lock()
+            .as_ref()
+            .is_some_and(|broadcast_data| broadcast_data.is_acked(peer, values))
+    })
 }
@@ -37,7 +37,7 @@ impl LRUCache {
         }
     }
 
-    pub fn add_val(&mut self, val: u64) -> () {
+    pub fn add_val(&mut self, val: u64) {
         if let Some(existing) = self.node_hashmap.get(&val) {
             let node = Rc::clone(existing);
             self.detach_node(&node);
@@ -58,7 +58,7 @@ impl LRUCache {
         }
     }
 
-    pub fn remove_last_used_val(&mut self) -> () {
+    pub fn remove_last_used_val(&mut self) {
         if let Some(tail) = self.linked_list.tail.clone() {
             let val = tail.borrow().val;
             self.detach_node(&tail);
@@ -1,20 +1,43 @@
+pub mod causal;
+pub mod digest;
 pub mod gossip;
+#[allow(dead_code)]
 pub mod lru_cache;
+pub mod pacing;
+pub mod plumtree;
+pub mod retract;
+pub mod sequence;
+pub mod targets;
 
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    io::Write,
-    thread,
-    time::Duration,
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
 
 use crate::{
-    BodyBase, Message,
-    challenges::{broadcast::gossip::GossipBody, cluster::global_cluster},
-    send,
+    BodyBase, Message, Payload,
+    challenges::{
+        broadcast::{
+            causal::CausalState,
+            gossip::GossipBody,
+            plumtree::PlumtreeState,
+            sequence::SequenceState,
+        },
+        cluster::{Cluster, global_cluster},
+        node::{MsgIdSpace, Node},
+    },
+    clock,
+    crdt::{Crdt, GSet, ORSet, Replicator, Tag},
+    handler::{Registry, route},
+    logging, metrics,
+    output::Output,
+    ranges::RangeSet,
+    scheduler, send, strict, timing, trace,
 };
 
 // ============================================================================
@@ -28,6 +51,11 @@ pub struct BroadcastBody {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub message: Option<u64>,
+
+    /// Node and sequence number the value was accepted under, in
+    /// `--sequence` mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<Tag>,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -37,6 +65,52 @@ pub struct ReadBody {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub messages: Option<HashSet<u64>>,
+
+    /// Reply encoding asked for by the client. Replies to clients that leave
+    /// it out carry plain `messages`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<ReplyEncoding>,
+
+    /// The set in `delta` encoding: sorted, with each value stored as its
+    /// difference from the previous one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub messages_delta: Option<Vec<u64>>,
+
+    /// Every value with its sequence tag, ordered by node and number, in
+    /// `--sequence` mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequences: Option<Vec<(u64, Tag)>>,
+
+    /// Key and value of a `read`/`read_ok` exchanged with a Maelstrom KV
+    /// service, which shares the `read` type with broadcast.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<serde_json::Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+}
+
+/// Compact encodings a client can request for large `read_ok` replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplyEncoding {
+    Delta,
+}
+
+/// Sorts `values` and replaces each with its gap from the previous one, which
+/// keeps the numbers short for the dense sets broadcast usually reads back.
+fn delta_encode(values: HashSet<u64>) -> Vec<u64> {
+    let mut sorted: Vec<u64> = values.into_iter().collect();
+    sorted.sort_unstable();
+    let mut previous = 0;
+    sorted
+        .into_iter()
+        .map(|value| {
+            let delta = value - previous;
+            previous = value;
+            delta
+        })
+        .collect()
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -52,19 +126,68 @@ pub struct TopologyBody {
 // Broadcast Data Store
 // ============================================================================
 
-#[derive(Debug, Clone, Default)]
+/// What a peer is known to hold and what is currently being sent to it.
+#[derive(Debug, Default)]
+pub struct PeerState {
+    /// Values the peer acknowledged or sent us itself.
+    pub known: RangeSet,
+    /// Values in gossip messages the peer has not acknowledged yet.
+    pub in_flight: HashSet<u64>,
+    /// Values from gossip messages that failed without an acknowledgement,
+    /// to go out again with the next delta. Their log positions are already
+    /// behind `frontier`.
+    pub requeued: HashSet<u64>,
+    /// Position in the insertion log up to which values have already been
+    /// considered for this peer.
+    pub frontier: usize,
+    /// When the peer last acknowledged one of our gossip messages.
+    pub last_ack: Option<Instant>,
+    /// Gossip messages to the peer resent after a timeout.
+    pub retransmits: u64,
+    /// Gossip messages to the peer still being retransmitted.
+    pub batches_in_flight: usize,
+}
+
+/// Values to send a peer, and the log position the peer's frontier moves to
+/// once they are claimed.
+#[derive(Debug, Default)]
+pub struct Delta {
+    pub values: HashSet<u64>,
+    pub frontier: usize,
+}
+
+#[derive(Debug)]
 pub struct BroadcastData {
-    pub data: HashSet<u64>,
-    pub seen_msg: HashSet<(String, u64)>,
-    pub last_gossip_len: usize,
+    data: GSet,
+    /// Per-peer delta tracking.
+    pub peers: Mutex<HashMap<String, PeerState>>,
+    /// Delivery order of values in `--causal` mode.
+    pub causal: CausalState,
+    /// Sequence tags of values in `--sequence` mode.
+    pub sequence: SequenceState,
+    /// Visible values in `--or-set` mode, where they can be retracted.
+    pub or_set: ORSet,
+    pub or_set_sync: Replicator,
+    /// Eager and lazy neighbours in `--plumtree` mode.
+    pub plumtree: PlumtreeState,
+}
+
+impl Default for BroadcastData {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl BroadcastData {
     pub fn new() -> Self {
         Self {
-            data: HashSet::new(),
-            seen_msg: HashSet::new(),
-            last_gossip_len: 0,
+            data: GSet::default(),
+            peers: Mutex::new(HashMap::new()),
+            causal: CausalState::default(),
+            sequence: SequenceState::default(),
+            or_set: ORSet::new(),
+            or_set_sync: Replicator::new(retract::RESYNC_ROUNDS),
+            plumtree: PlumtreeState::default(),
         }
     }
 
@@ -73,120 +196,402 @@ impl BroadcastData {
     }
 
     pub fn extend(&mut self, values: HashSet<u64>) {
-        self.data.extend(values);
+        for value in values {
+            self.insert(value);
+        }
+    }
+
+    pub fn contains(&self, value: u64) -> bool {
+        self.data.values().contains(value)
     }
 
     pub fn clone_data(&self) -> HashSet<u64> {
-        self.data.clone()
+        self.data.value()
+    }
+
+    /// Values added since `peer_known`'s frontier that the peer neither holds
+    /// nor is already being sent. Costs O(values added since the frontier),
+    /// or O(values held) when compaction dropped the log that far back.
+    pub fn diff_against(&self, peer_known: &PeerState) -> Delta {
+        let held: Vec<u64>;
+        let added = match self.data.added_since(peer_known.frontier as u64) {
+            Some(added) => added,
+            None => {
+                held = self.data.values().iter().collect();
+                &held
+            }
+        };
+        let values = added
+            .iter()
+            .chain(&peer_known.requeued)
+            .filter(|value| {
+                !peer_known.known.contains(**value) && !peer_known.in_flight.contains(value)
+            })
+            .copied()
+            .collect();
+        Delta {
+            values,
+            frontier: self.data.version() as usize,
+        }
+    }
+
+    /// Claims `peer`'s delta: its values are marked as in flight and its
+    /// frontier advanced, so the next round doesn't send them again. Nothing
+    /// is claimed while `max_batches` gossip messages to the peer are still
+    /// unacknowledged; the values wait and go out together once one is.
+    pub fn take_delta(&self, peer: &str, max_batches: usize) -> HashSet<u64> {
+        let mut peers = self.peers.lock().expect("peer state poisoned");
+        let state = peers.entry(peer.to_string()).or_default();
+        if state.batches_in_flight >= max_batches {
+            return HashSet::new();
+        }
+        let delta = self.diff_against(state);
+        state.in_flight.extend(&delta.values);
+        state.requeued.clear();
+        state.frontier = delta.frontier;
+        if !delta.values.is_empty() {
+            state.batches_in_flight += 1;
+        }
+        delta.values
+    }
+
+    /// Values added since `peer`'s frontier that it isn't known to hold, for
+    /// an announcement to a lazy neighbour. The frontier moves past them but
+    /// nothing is put in flight; the peer grafts whatever it still needs.
+    pub fn take_announcement(&self, peer: &str) -> HashSet<u64> {
+        let mut peers = self.peers.lock().expect("peer state poisoned");
+        let state = peers.entry(peer.to_string()).or_default();
+        let delta = self.diff_against(state);
+        state.frontier = delta.frontier;
+        delta.values
+    }
+
+    /// Claims `values` found missing at `peer` outside its delta, e.g. by
+    /// anti-entropy, as one more batch in flight. Values already in flight
+    /// are left out.
+    pub fn claim(&self, peer: &str, values: HashSet<u64>) -> HashSet<u64> {
+        let mut peers = self.peers.lock().expect("peer state poisoned");
+        let state = peers.entry(peer.to_string()).or_default();
+        let values: HashSet<u64> = values
+            .into_iter()
+            .filter(|value| !state.in_flight.contains(value))
+            .collect();
+        for value in &values {
+            state.known.remove(*value);
+        }
+        state.in_flight.extend(&values);
+        if !values.is_empty() {
+            state.batches_in_flight += 1;
+        }
+        values
     }
 
-    pub fn add_if_not_present(&mut self, origin: &str, msg_id: u64) -> bool {
-        let key = (origin.to_string(), msg_id);
-        if self.seen_msg.contains(&key) {
-            false
-        } else {
-            self.seen_msg.insert(key);
-            true
+    /// Takes `values` back out of flight to `peer` after their gossip message
+    /// failed without an acknowledgement, so the next delta sends them again.
+    pub fn release(&self, peer: &str, values: &HashSet<u64>) {
+        let mut peers = self.peers.lock().expect("peer state poisoned");
+        let Some(state) = peers.get_mut(peer) else {
+            return;
+        };
+        for value in values {
+            if state.in_flight.remove(value) && !state.known.contains(*value) {
+                state.requeued.insert(*value);
+            }
+        }
+    }
+
+    /// Records that a gossip message to `peer` stopped being retransmitted.
+    pub fn finish_batch(&self, peer: &str) {
+        let mut peers = self.peers.lock().expect("peer state poisoned");
+        if let Some(state) = peers.get_mut(peer) {
+            state.batches_in_flight = state.batches_in_flight.saturating_sub(1);
+        }
+    }
+
+    /// Records that `peer` holds `values`, either because it acknowledged
+    /// them or because it gossiped them to us.
+    pub fn record_ack(&self, peer: &str, values: &HashSet<u64>) {
+        let mut peers = self.peers.lock().expect("peer state poisoned");
+        let state = peers.entry(peer.to_string()).or_default();
+        state.in_flight.retain(|value| !values.contains(value));
+        state.known.extend(values.iter().copied());
+    }
+
+    /// Records `peer`'s acknowledgement of a gossip message carrying `values`.
+    pub fn record_gossip_ok(&self, peer: &str, values: &HashSet<u64>) {
+        self.record_ack(peer, values);
+        let mut peers = self.peers.lock().expect("peer state poisoned");
+        peers.entry(peer.to_string()).or_default().last_ack = Some(clock::now());
+    }
+
+    pub fn record_retransmit(&self, peer: &str) {
+        let mut peers = self.peers.lock().expect("peer state poisoned");
+        peers.entry(peer.to_string()).or_default().retransmits += 1;
+    }
+
+    /// Whether `peer` is known to hold every one of `values`.
+    pub fn is_acked(&self, peer: &str, values: &HashSet<u64>) -> bool {
+        let peers = self.peers.lock().expect("peer state poisoned");
+        peers
+            .get(peer)
+            .is_some_and(|state| values.iter().all(|value| state.known.contains(*value)))
+    }
+
+    /// Entries held for the values, every peer's tracking and the OR-Set
+    /// tombstones not yet collected.
+    pub fn size_estimate(&self) -> usize {
+        let peers = self.peers.lock().expect("peer state poisoned");
+        let tracked: usize = peers
+            .values()
+            .map(|state| state.known.entries() + state.in_flight.len() + state.requeued.len())
+            .sum();
+        self.data.entries() + tracked + self.or_set.tombstone_count()
+    }
+
+    /// Folds consecutive values, and those each peer is known to hold, into
+    /// runs, and drops the insertion log behind every peer's frontier.
+    /// Returns how many entries were freed.
+    pub fn compact(&mut self) -> usize {
+        let mut peers = self.peers.lock().expect("peer state poisoned");
+        let mut freed = 0;
+        for state in peers.values_mut() {
+            freed += state.known.compact();
+            state.in_flight.shrink_to_fit();
+            state.requeued.shrink_to_fit();
+        }
+        let oldest_frontier = peers.values().map(|state| state.frontier as u64).min();
+        drop(peers);
+        let version = oldest_frontier.unwrap_or(self.data.version());
+        freed + self.data.compact(version)
+    }
+
+    /// Forgets the delta state of peers no longer in `current`, e.g. after a
+    /// topology change.
+    pub fn retain_peers(&self, current: &[String]) {
+        let mut peers = self.peers.lock().expect("peer state poisoned");
+        peers.retain(|peer, _| current.contains(peer));
+    }
+
+    /// Values we hold that `peer` is not known to hold, to piggyback on a
+    /// reply to its gossip. Values already in flight to it are left to their
+    /// own gossip message, whose retransmissions deliver them anyway.
+    pub fn missing_for(&self, peer: &str) -> HashSet<u64> {
+        let peers = self.peers.lock().expect("peer state poisoned");
+        match peers.get(peer) {
+            Some(state) => {
+                let values = self.data.values().iter();
+                let (known, in_flight) = (&state.known, &state.in_flight);
+                values
+                    .filter(|value| !known.contains(*value) && !in_flight.contains(value))
+                    .collect()
+            }
+            None => self.data.value(),
         }
     }
 }
 
 // ============================================================================
-// Gossip Thread
+// Gossip Task
 // ============================================================================
 
-const GOSSIP_INTERVAL_MS: u64 = 50;
-
-fn spawn_gossip_thread(node_id: String) -> thread::JoinHandle<()> {
-    thread::spawn(move || {
-        loop {
-            thread::sleep(Duration::from_millis(GOSSIP_INTERVAL_MS));
+/// Starts the node's gossip task unless it is already running.
+fn ensure_gossip_task(node: &Node, cluster: &Cluster, output: &Output) {
+    node.gossip_task.get_or_init(|| {
+        let (tick, adaptive) = (cluster.config.gossip_tick, cluster.config.adaptive_gossip);
+        spawn_gossip_task(node, tick, adaptive, output.clone())
+    });
+}
 
-            let Some((src, data, peers)) = prepare_gossip_batch(&node_id) else {
-                continue;
-            };
+/// Starts gossiping the values a restarted node reloaded from its
+/// write-ahead log, in case they hadn't reached every peer.
+pub fn restore(node: &Node, cluster: &Cluster, output: &Output) {
+    if node.broadcast_data.lock().is_some() {
+        ensure_gossip_task(node, cluster, output);
+    }
+}
 
-            if peers.is_empty() {
-                continue;
+/// Starts the task that batches values accepted since the last tick and
+/// flushes each peer's delta every `tick`, or at the pace set by the node's
+/// `GossipPacer` in `--adaptive-gossip` mode.
+fn spawn_gossip_task(
+    node: &Node,
+    tick: Duration,
+    adaptive: bool,
+    output: Output,
+) -> JoinHandle<()> {
+    let node_id = node.id.clone();
+    if !adaptive {
+        return scheduler::every("gossip_timer", tick, move |round| {
+            let node_id = node_id.clone();
+            let output = output.clone();
+            async move {
+                gossip_round(&node_id, round, &output).await;
             }
+        });
+    }
 
-            send_gossip_to_peers(&src, &data, &peers, rand::random::<u64>(), &src);
+    let pacer = node.gossip_pacer.clone();
+    scheduler::paced(pacer.wake(), move |round| {
+        let node_id = node_id.clone();
+        let output = output.clone();
+        let pacer = pacer.clone();
+        async move {
+            let fanout = gossip_round(&node_id, round, &output).await;
+            pacer.next_delay(tick, fanout)
         }
     })
 }
 
-pub fn prepare_gossip_batch(node_id: &str) -> Option<(String, HashSet<u64>, Vec<(String, u64)>)> {
-    let mut cluster = global_cluster().write().unwrap();
-    let node = cluster.get_node_mut(node_id)?;
-
-    let broadcast_data = node.broadcast_data.get_or_insert_with(BroadcastData::new);
-    let gossip_data = broadcast_data.clone_data();
-    if gossip_data.len() == broadcast_data.last_gossip_len {
-        return None;
+/// Sends one round of gossip and returns how many peers it went to.
+async fn gossip_round(node_id: &str, round: u64, output: &Output) -> usize {
+    let started = clock::now();
+    let (batch, timings) = timing::measure(async { prepare_gossip_batch(node_id, round) }).await;
+    let fanout = batch.len();
+    metrics::record_gossip_round(fanout);
+    let args = serde_json::json!({
+        "node": node_id,
+        "round": round,
+        "fanout": fanout,
+        "prepare_us": clock::elapsed(started).as_micros() as u64,
+        "lock_wait_us": timings.lock_wait.as_micros() as u64,
+    });
+    if trace::is_enabled() {
+        trace::instant("gossip_tick", "gossip", trace::GOSSIP_TRACK, args.clone());
     }
-    broadcast_data.last_gossip_len = gossip_data.len();
-    let src = node.id.clone();
-    let node_id_owned = node.id.clone();
-
-    // Clone peer list to avoid borrow conflicts
-    let peer_list: Vec<String> = node.peers.clone();
+    if !batch.is_empty() {
+        logging::event(logging::Level::Debug, "gossip_round", args);
+    }
+    for message in batch {
+        gossip::send_gossip(message, output);
+    }
+    if or_set_mode() {
+        for message in retract::sync_messages(node_id, round) {
+            let _ = send(&message, output);
+        }
+    }
+    for message in digest::digest_messages(node_id, round) {
+        let _ = send(&message, output);
+    }
+    for message in plumtree::round_messages(node_id) {
+        let _ = send(&message, output);
+    }
+    fanout
+}
 
-    // Generate message IDs for each peer (excluding self)
-    let peers: Vec<(String, u64)> = peer_list
-        .into_iter()
-        .filter(|peer| peer != &node_id_owned)
-        .map(|peer| {
-            let msg_id = node.get_next_id();
-            (peer, msg_id)
-        })
-        .collect();
+fn or_set_mode() -> bool {
+    global_cluster()
+        .read()
+        .expect("cluster lock poisoned")
+        .config
+        .or_set
+}
 
-    Some((src, gossip_data, peers))
+/// Gossip messages carrying each target's delta for one periodic round.
+pub fn prepare_gossip_batch(node_id: &str, round: u64) -> Vec<Message<Payload>> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let Some(node) = cluster.get_node(node_id) else {
+        return Vec::new();
+    };
+    let strategy = cluster.config.gossip_targets;
+    let limit = strategy.limit(cluster.config.gossip_fanout);
+    let max_batches = cluster.config.max_in_flight;
+    let mut targets = strategy.candidates(node, round);
+    if cluster.config.plumtree
+        && let Some(broadcast_data) = node.broadcast_data.lock().as_ref()
+    {
+        targets.retain(|peer| broadcast_data.plumtree.is_eager(peer));
+    }
+    // A dead peer's delta waits until the failure detector hears from it.
+    targets.retain(|peer| !node.membership.is_dead(peer));
+    gossip_deltas(node, &targets, limit, max_batches, rand::random::<u64>(), &node.id)
 }
 
-pub fn send_gossip_to_peers(
-    src: &str,
-    data: &HashSet<u64>,
-    peers: &[(String, u64)],
+/// Builds one gossip message for each of the first `limit` targets with
+/// values they are missing, skipping targets that are already up to date.
+fn gossip_deltas(
+    node: &Node,
+    targets: &[String],
+    limit: usize,
+    max_batches: usize,
     org_msg_id: u64,
     org_msg_src: &str,
-) {
-    let mut stdout = std::io::stdout().lock();
-
-    for (peer, msg_id) in peers {
-        let message = create_gossip_message(
-            src,
-            peer,
-            *msg_id,
-            data.clone(),
-            org_msg_id,
-            org_msg_src,
-        );
-        let _ = send(&message, &mut stdout);
+) -> Vec<Message<Payload>> {
+    let broadcast_data = node.broadcast_data.lock();
+    let Some(broadcast_data) = broadcast_data.as_ref() else {
+        return Vec::new();
+    };
+
+    targets
+        .iter()
+        .filter_map(|peer| {
+            let delta = broadcast_data.take_delta(peer, max_batches);
+            if delta.is_empty() {
+                return None;
+            }
+            Some(create_gossip_message(
+                &node.id,
+                peer,
+                node.get_next_id(MsgIdSpace::Gossip),
+                delta,
+                broadcast_data,
+                org_msg_id,
+                org_msg_src,
+            ))
+        })
+        .take(limit)
+        .collect()
+}
+
+/// Gossip to `peer` carrying those of `values` not already on their way to
+/// it, claimed as one more batch in flight, or `None` if there are none.
+fn gossip_missing(
+    node: &Node,
+    broadcast_data: &BroadcastData,
+    peer: &str,
+    values: HashSet<u64>,
+) -> Option<Message<Payload>> {
+    let values = broadcast_data.claim(peer, values);
+    if values.is_empty() {
+        return None;
     }
+    Some(create_gossip_message(
+        &node.id,
+        peer,
+        node.get_next_id(MsgIdSpace::Gossip),
+        values,
+        broadcast_data,
+        rand::random::<u64>(),
+        &node.id,
+    ))
 }
 
+/// Gossip carrying `data` along with its causal stamps and sequence tags.
 fn create_gossip_message(
     src: &str,
     dest: &str,
     msg_id: u64,
     data: HashSet<u64>,
+    broadcast_data: &BroadcastData,
     org_msg_id: u64,
     org_msg_src: &str,
-) -> Message<GossipBody> {
+) -> Message<Payload> {
+    let causal_stamps = broadcast_data.causal.stamps_for(&data);
+    let sequences = broadcast_data.sequence.tags_for(&data);
     Message {
         src: src.to_string(),
         dest: dest.to_string(),
-        body: GossipBody {
+        body: Payload::Gossip(GossipBody {
             base: BodyBase {
-                typ: "gossip".to_string(),
                 msg_id: Some(msg_id),
                 in_reply_to: None,
             },
             gossip_data: Some(data),
+            causal_stamps: (!causal_stamps.is_empty()).then_some(causal_stamps),
+            sequences: (!sequences.is_empty()).then_some(sequences),
+            prune: false,
             org_msg_id,
             org_msg_src: org_msg_src.to_string(),
-        },
+        }),
     }
 }
 
@@ -194,109 +599,130 @@ fn create_gossip_message(
 // Message Handlers
 // ============================================================================
 
-pub fn broadcast(msg: Message<BroadcastBody>, output: &mut impl Write) -> Result<()> {
-    let (response, gossip_messages) = {
-        let mut cluster = global_cluster().write().unwrap();
-        let node = cluster.get_node_mut(&msg.dest).unwrap();
+pub fn register(registry: &mut Registry) {
+    registry.register("broadcast", route!(Payload::Broadcast => broadcast));
+    registry.register("read", route!(Payload::Read => read));
+    registry.register("topology", route!(Payload::Topology => topology));
+    registry.register("gossip", route!(Payload::Gossip => gossip::gossip));
+    registry.register("gossip_status", route!(Payload::GossipStatus => gossip::gossip_status));
+    registry.register("retract", route!(Payload::Retract => retract::retract));
+    registry.register("or_set_sync", route!(Payload::OrSetSync => retract::or_set_sync));
+    registry.register("digest", route!(Payload::Digest => digest::digest_request));
+    registry.register("digest_diff", route!(Payload::DigestDiff => digest::digest_diff));
+    registry.register("ihave", route!(Payload::IHave => plumtree::ihave));
+    registry.register("graft", route!(Payload::Graft => plumtree::graft));
+}
 
-        // Initialize broadcast data if needed
-        let broadcast_data = node.broadcast_data.get_or_insert_with(BroadcastData::new);
+pub async fn broadcast(msg: Message<BroadcastBody>, output: &Output) -> Result<()> {
+    let response = {
+        let cluster = global_cluster().read().expect("cluster lock poisoned");
+        let node = cluster
+            .get_node(&msg.dest)
+            .context("node not found in cluster")?;
 
         // Store the incoming message
+        metrics::record_broadcast();
+        let mut tag = None;
         if let Some(value) = msg.body.message {
+            let mut broadcast_data = node.broadcast_data.lock();
+            let broadcast_data = broadcast_data.get_or_insert_with(BroadcastData::new);
+            // Logged before it's acknowledged, so an acknowledged value
+            // survives a crash, and with the state locked, so a checkpoint
+            // can't miss it.
+            node.log(&[value])?;
+            if cluster.config.sequence {
+                tag = Some(broadcast_data.sequence.assign(&node.id, value));
+            }
             broadcast_data.insert(value);
+            if cluster.config.causal {
+                broadcast_data.causal.broadcast(&node.id, value);
+            }
+            if cluster.config.or_set {
+                let seq = node.get_next_id(MsgIdSpace::OrSetTag);
+                retract::add(broadcast_data, &node.id, seq, value);
+            }
+            node.gossip_pacer.record_pending(1);
         }
 
-        // Spawn gossip thread on first broadcast
-        if node.gossip_thread.is_none() {
-            let handle = spawn_gossip_thread(node.id.clone());
-            node.gossip_thread = Some(handle.thread().clone());
-        }
-
-        // Prepare gossip messages for all peers
-        let gossip_data = broadcast_data.clone_data();
-        broadcast_data.last_gossip_len = gossip_data.len();
-        let node_id = node.id.clone();
-
-        let peer_list: Vec<String> = node
-            .peers
-            .iter()
-            .filter(|peer| *peer != &node_id)
-            .cloned()
-            .collect();
-
-        let gossip_messages: Vec<_> = peer_list
-            .into_iter()
-            .map(|peer| {
-                let msg_id = node.get_next_id();
-                create_gossip_message(
-                    &node_id,
-                    &peer,
-                    msg_id,
-                    gossip_data.clone(),
-                    msg.body.base.msg_id.unwrap(),
-                    &msg.src,
-                )
-            })
-            .collect();
+        // Spawn gossip task on first broadcast; the value goes out with the
+        // next batch
+        ensure_gossip_task(node, &cluster, output);
 
-        // Build response
-        let response = Message {
+        Message {
             src: node.id.clone(),
             dest: msg.src.clone(),
-            body: BroadcastBody {
+            body: Payload::BroadcastOk(BroadcastBody {
                 base: BodyBase {
-                    typ: "broadcast_ok".to_string(),
-                    msg_id: Some(node.get_next_id()),
+                    msg_id: Some(node.get_next_id(MsgIdSpace::Client)),
                     in_reply_to: msg.body.base.msg_id,
                 },
                 message: None,
-            },
-        };
-
-        (response, gossip_messages)
+                sequence: tag,
+            }),
+        }
     };
 
-    // Send all messages outside the lock
-    for gossip_msg in gossip_messages {
-        send(&gossip_msg, output)?;
-    }
     send(&response, output)
 }
 
-pub fn read(msg: Message<ReadBody>, output: &mut impl Write) -> Result<()> {
+pub async fn read(msg: Message<ReadBody>, output: &Output) -> Result<()> {
     let response = {
-        let mut cluster = global_cluster().write().unwrap();
-        let node = cluster.get_node_mut(&msg.dest).unwrap();
+        let cluster = global_cluster().read().expect("cluster lock poisoned");
+        let node = cluster
+            .get_node(&msg.dest)
+            .context("node not found in cluster")?;
+
+        let broadcast_data = node.broadcast_data.lock();
+        let messages = match broadcast_data.as_ref() {
+            Some(broadcast_data) if cluster.config.or_set => broadcast_data.or_set.elements(),
+            Some(broadcast_data) if cluster.config.causal => broadcast_data.causal.visible(),
+            Some(broadcast_data) => broadcast_data.clone_data(),
+            None => HashSet::new(),
+        };
+        // Retracts shrink the set in OR-Set mode
+        if strict::is_enabled() && !cluster.config.or_set {
+            strict::check_monotonic(&node.id, "broadcast set size", messages.len() as u64);
+        }
 
-        let broadcast_data = node.broadcast_data.get_or_insert_with(BroadcastData::new);
-        let messages = broadcast_data.clone_data();
+        let encoding = msg.body.encoding;
+        let (messages, messages_delta) = match encoding {
+            Some(ReplyEncoding::Delta) => (None, Some(delta_encode(messages))),
+            None => (Some(messages), None),
+        };
 
         Message {
             src: node.id.clone(),
             dest: msg.src.clone(),
-            body: ReadBody {
+            body: Payload::ReadOk(ReadBody {
                 base: BodyBase {
-                    typ: "read_ok".to_string(),
-                    msg_id: Some(node.get_next_id()),
+                    msg_id: Some(node.get_next_id(MsgIdSpace::Client)),
                     in_reply_to: msg.body.base.msg_id,
                 },
-                messages: Some(messages),
-            },
+                messages,
+                encoding,
+                messages_delta,
+                sequences: broadcast_data
+                    .as_ref()
+                    .filter(|_| cluster.config.sequence)
+                    .map(|broadcast_data| broadcast_data.sequence.ordered()),
+                ..Default::default()
+            }),
         }
     };
 
     send(&response, output)
 }
 
-pub fn topology(msg: Message<TopologyBody>, output: &mut impl Write) -> Result<()> {
+pub async fn topology(msg: Message<TopologyBody>, output: &Output) -> Result<()> {
     let response = {
-        let mut cluster = global_cluster().write().unwrap();
-        let node = cluster.get_node_mut(&msg.dest).unwrap();
+        let mut cluster = global_cluster().write().expect("cluster lock poisoned");
+        let node = cluster
+            .get_node_mut(&msg.dest)
+            .context("node not found in cluster")?;
         let node_id = node.id.clone();
-        let all_nodes = node.peers.clone();
+        let all_nodes = topology_members(&msg.body, &node.node_ids);
 
-        if !cluster.is_topology_done {
+        if !(cluster.config.freeze_topology && cluster.is_topology_done) {
             let graph = build_optimized_topology(&all_nodes);
             apply_topology_to_cluster(&mut cluster, &graph, &all_nodes);
             cluster.is_topology_done = true;
@@ -305,14 +731,13 @@ pub fn topology(msg: Message<TopologyBody>, output: &mut impl Write) -> Result<(
         Message {
             src: node_id,
             dest: msg.src.clone(),
-            body: TopologyBody {
+            body: Payload::TopologyOk(TopologyBody {
                 base: BodyBase {
-                    typ: "topology_ok".to_string(),
                     msg_id: None,
                     in_reply_to: msg.body.base.msg_id,
                 },
                 topology: None,
-            },
+            }),
         }
     };
 
@@ -323,21 +748,38 @@ pub fn topology(msg: Message<TopologyBody>, output: &mut impl Write) -> Result<(
 // Topology Building
 // ============================================================================
 
+/// Nodes to build the peer graph over: the members named by the topology
+/// message, or the init membership if it names none. Sorted so that every
+/// node derives the same graph.
+fn topology_members(body: &TopologyBody, node_ids: &[String]) -> Vec<String> {
+    let mut members: Vec<String> = match &body.topology {
+        Some(topology) if !topology.is_empty() => topology.keys().cloned().collect(),
+        _ => node_ids.to_vec(),
+    };
+    members.sort();
+    members
+}
+
 /// Builds an optimized topology graph where all nodes are within 2 hops of each other.
+///
+/// Adjacency is mirrored in one bitset per node, so checking whether two
+/// nodes are within two hops is a word-wise AND of their neighbour sets
+/// instead of a BFS, keeping construction fast for clusters of hundreds of
+/// nodes.
 fn build_optimized_topology(nodes: &[String]) -> HashMap<String, Vec<String>> {
     let mut graph: HashMap<String, Vec<String>> = HashMap::new();
+    let mut adjacency = vec![Bitset::new(nodes.len()); nodes.len()];
 
     // First pass: create a linear chain
-    for window in nodes.windows(2) {
-        let (a, b) = (&window[0], &window[1]);
-        add_bidirectional_edge(&mut graph, a, b);
+    for i in 1..nodes.len() {
+        connect(&mut graph, &mut adjacency, nodes, i - 1, i);
     }
 
     // Second pass: add shortcut edges for nodes more than 2 hops apart
     for i in 0..nodes.len() {
         for j in (i + 1)..nodes.len() {
-            if !is_within_two_hops(&graph, &nodes[i], &nodes[j]) {
-                add_bidirectional_edge(&mut graph, &nodes[i], &nodes[j]);
+            if !is_within_two_hops(&adjacency, i, j) {
+                connect(&mut graph, &mut adjacency, nodes, i, j);
             }
         }
     }
@@ -345,6 +787,18 @@ fn build_optimized_topology(nodes: &[String]) -> HashMap<String, Vec<String>> {
     graph
 }
 
+fn connect(
+    graph: &mut HashMap<String, Vec<String>>,
+    adjacency: &mut [Bitset],
+    nodes: &[String],
+    i: usize,
+    j: usize,
+) {
+    add_bidirectional_edge(graph, &nodes[i], &nodes[j]);
+    adjacency[i].insert(j);
+    adjacency[j].insert(i);
+}
+
 fn add_bidirectional_edge(graph: &mut HashMap<String, Vec<String>>, a: &str, b: &str) {
     graph.entry(a.to_string()).or_default().push(b.to_string());
     graph.entry(b.to_string()).or_default().push(a.to_string());
@@ -358,38 +812,268 @@ fn apply_topology_to_cluster(
     for node_id in nodes {
         if let Some(node) = cluster.get_node_mut(node_id) {
             node.peers = graph.get(node_id).cloned().unwrap_or_default();
+            node.distances = targets::hop_distances(graph, node_id);
         }
     }
 }
 
-/// Checks if two nodes are within 2 hops of each other using BFS.
-fn is_within_two_hops(graph: &HashMap<String, Vec<String>>, start: &str, target: &str) -> bool {
-    if start == target {
-        return true;
+/// Checks if two nodes are within 2 hops of each other: directly connected,
+/// or sharing a neighbour.
+fn is_within_two_hops(adjacency: &[Bitset], a: usize, b: usize) -> bool {
+    a == b || adjacency[a].contains(b) || adjacency[a].intersects(&adjacency[b])
+}
+
+/// Fixed-size set of node indices.
+#[derive(Debug, Clone)]
+struct Bitset {
+    words: Vec<u64>,
+}
+
+impl Bitset {
+    fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+        }
     }
 
-    let mut visited = HashSet::new();
-    let mut queue = VecDeque::new();
+    fn insert(&mut self, index: usize) {
+        self.words[index / 64] |= 1 << (index % 64);
+    }
 
-    visited.insert(start.to_string());
-    queue.push_back((start.to_string(), 0));
+    fn contains(&self, index: usize) -> bool {
+        self.words[index / 64] & (1 << (index % 64)) != 0
+    }
 
-    while let Some((current, depth)) = queue.pop_front() {
-        if depth >= 2 {
-            continue;
-        }
+    fn intersects(&self, other: &Bitset) -> bool {
+        self.words.iter().zip(&other.words).any(|(a, b)| a & b != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn values(values: &[u64]) -> HashSet<u64> {
+        values.iter().copied().collect()
+    }
+
+    #[test]
+    fn delta_skips_values_in_flight_and_known() {
+        let mut data = BroadcastData::new();
+        data.extend(values(&[1, 2]));
+        assert_eq!(data.take_delta("n2", usize::MAX), values(&[1, 2]));
+
+        data.insert(3);
+        data.record_ack("n2", &values(&[3]));
+        data.insert(4);
+        assert_eq!(data.take_delta("n2", usize::MAX), values(&[4]));
+        assert!(data.take_delta("n2", usize::MAX).is_empty());
+    }
+
+    #[test]
+    fn piggyback_skips_values_in_flight_and_known() {
+        let mut data = BroadcastData::new();
+        data.extend(values(&[1, 2, 3]));
+        assert_eq!(data.missing_for("n2"), values(&[1, 2, 3]));
 
-        if let Some(neighbors) = graph.get(&current) {
-            for neighbor in neighbors {
-                if neighbor == target {
-                    return true;
+        data.insert(4);
+        assert_eq!(data.take_delta("n2", usize::MAX), values(&[1, 2, 3, 4]));
+        data.record_ack("n2", &values(&[1]));
+        data.insert(5);
+        // 2, 3 and 4 are still in flight to n2 and get retransmitted.
+        assert_eq!(data.missing_for("n2"), values(&[5]));
+    }
+
+    #[test]
+    fn released_values_go_out_with_the_next_delta() {
+        let mut data = BroadcastData::new();
+        data.extend(values(&[1, 2, 3]));
+        let sent = data.take_delta("n2", usize::MAX);
+        data.record_ack("n2", &values(&[1]));
+
+        // The gossip failed outright: nothing more will ack 2 and 3.
+        data.release("n2", &sent);
+        data.finish_batch("n2");
+        data.insert(4);
+        assert_eq!(data.take_delta("n2", usize::MAX), values(&[2, 3, 4]));
+        assert!(data.take_delta("n2", usize::MAX).is_empty());
+    }
+
+    fn node_names(count: usize) -> Vec<String> {
+        let mut nodes: Vec<String> = (0..count).map(|i| format!("n{i}")).collect();
+        nodes.sort();
+        nodes
+    }
+
+    /// The graph built with a BFS per node pair, as before the bitsets.
+    fn bfs_topology(nodes: &[String]) -> HashMap<String, Vec<String>> {
+        let mut graph: HashMap<String, Vec<String>> = HashMap::new();
+        for window in nodes.windows(2) {
+            add_bidirectional_edge(&mut graph, &window[0], &window[1]);
+        }
+        for i in 0..nodes.len() {
+            for j in (i + 1)..nodes.len() {
+                if !bfs_within_two_hops(&graph, &nodes[i], &nodes[j]) {
+                    add_bidirectional_edge(&mut graph, &nodes[i], &nodes[j]);
                 }
+            }
+        }
+        graph
+    }
+
+    fn bfs_within_two_hops(graph: &HashMap<String, Vec<String>>, start: &str, target: &str) -> bool {
+        let mut visited = HashSet::from([start.to_string()]);
+        let mut queue = std::collections::VecDeque::from([(start.to_string(), 0)]);
+        while let Some((current, depth)) = queue.pop_front() {
+            if current == target {
+                return true;
+            }
+            if depth == 2 {
+                continue;
+            }
+            for neighbor in graph.get(&current).into_iter().flatten() {
                 if visited.insert(neighbor.clone()) {
                     queue.push_back((neighbor.clone(), depth + 1));
                 }
             }
         }
+        false
+    }
+
+    #[test]
+    fn topology_matches_the_bfs_construction() {
+        for count in (0..=40).chain([63, 64, 65, 80]) {
+            let nodes = node_names(count);
+            assert_eq!(build_optimized_topology(&nodes), bfs_topology(&nodes), "{count} nodes");
+        }
+    }
+
+    #[test]
+    fn every_pair_is_within_two_hops() {
+        let nodes = node_names(300);
+        let graph = build_optimized_topology(&nodes);
+        let neighbours: HashMap<&String, HashSet<&String>> = graph
+            .iter()
+            .map(|(node, peers)| (node, peers.iter().collect()))
+            .collect();
+        for a in &nodes {
+            for b in &nodes {
+                let close = a == b
+                    || neighbours[a].contains(b)
+                    || !neighbours[a].is_disjoint(&neighbours[b]);
+                assert!(close, "{a} and {b} are more than two hops apart");
+            }
+        }
     }
 
-    false
+    /// Construction time for large clusters:
+    /// `cargo test --release topology_build_time -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn topology_build_time() {
+        for count in [100, 500, 1000] {
+            let nodes = node_names(count);
+            let started = Instant::now();
+            let graph = build_optimized_topology(&nodes);
+            let edges: usize = graph.values().map(Vec::len).sum::<usize>() / 2;
+            println!("{count} nodes: {edges} edges in {:?}", started.elapsed());
+        }
+    }
+
+    #[test]
+    fn delta_waits_for_a_free_batch() {
+        let mut data = BroadcastData::new();
+        data.insert(1);
+        assert_eq!(data.take_delta("n2", 1), values(&[1]));
+        data.insert(2);
+        assert!(data.take_delta("n2", 1).is_empty());
+        data.finish_batch("n2");
+        assert_eq!(data.take_delta("n2", 1), values(&[2]));
+    }
+
+    /// Applies random inserts, gossip bookkeeping for three peers and
+    /// compactions. After every step the held values only grow, nothing is
+    /// both in flight and known, and every held value a peer isn't known to
+    /// hold is in flight, requeued or still ahead of its frontier, so some
+    /// later delta sends it. Compaction frees what it reports and keeps
+    /// every value.
+    #[test]
+    fn random_operations_never_lose_a_value_for_a_peer() {
+        let peers = ["n1", "n2", "n3"];
+        for seed in 0..200 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut data = BroadcastData::new();
+            let mut sent: HashMap<&str, Vec<HashSet<u64>>> = HashMap::new();
+            let mut held = HashSet::new();
+            for step in 0..100 {
+                let peer = peers[rng.random_range(0..peers.len())];
+                let batches = sent.entry(peer).or_default();
+                match rng.random_range(0..10) {
+                    0..3 => data.insert(rng.random_range(0..50)),
+                    3..5 => batches.push(data.take_delta(peer, rng.random_range(1..3))),
+                    5 if !batches.is_empty() => {
+                        let batch = batches.remove(rng.random_range(0..batches.len()));
+                        data.record_gossip_ok(peer, &batch);
+                        data.finish_batch(peer);
+                    }
+                    6 if !batches.is_empty() => {
+                        let batch = batches.remove(rng.random_range(0..batches.len()));
+                        data.release(peer, &batch);
+                        data.finish_batch(peer);
+                    }
+                    7 => {
+                        let gossiped = values(&[rng.random_range(0..50)]);
+                        data.record_ack(peer, &gossiped);
+                        data.extend(gossiped);
+                    }
+                    8 if rng.random_bool(0.5) => {
+                        let missing = data.missing_for(peer);
+                        batches.push(data.claim(peer, missing));
+                    }
+                    8 => {
+                        let (before, contents) = (data.size_estimate(), data.clone_data());
+                        let freed = data.compact();
+                        assert_eq!(data.size_estimate(), before - freed, "seed {seed}");
+                        assert_eq!(data.clone_data(), contents, "seed {seed} step {step}");
+                    }
+                    _ => {
+                        let rest: Vec<String> = peers
+                            .iter()
+                            .filter(|other| **other != peer)
+                            .map(|other| other.to_string())
+                            .collect();
+                        data.retain_peers(&rest);
+                    }
+                }
+
+                let now = data.clone_data();
+                assert!(held.is_subset(&now), "seed {seed} step {step}");
+                held = now;
+                let states = data.peers.lock().unwrap();
+                for (peer, state) in states.iter() {
+                    assert!(
+                        state.frontier as u64 <= data.data.version(),
+                        "seed {seed} step {step}: {peer}"
+                    );
+                    let known = &state.known;
+                    assert!(
+                        state.in_flight.iter().all(|value| !known.contains(*value)),
+                        "seed {seed} step {step}: {peer}"
+                    );
+                    // Without the log, the next delta looks at every value.
+                    let ahead = data.data.added_since(state.frontier as u64);
+                    for value in &held {
+                        let covered = state.known.contains(*value)
+                            || state.in_flight.contains(value)
+                            || state.requeued.contains(value)
+                            || ahead.is_none_or(|ahead| ahead.contains(value));
+                        assert!(covered, "seed {seed} step {step}: {peer} lost {value}");
+                    }
+                }
+            }
+        }
+    }
 }
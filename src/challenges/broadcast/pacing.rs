@@ -0,0 +1,59 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+// ============================================================================
+// Adaptive Gossip Pacing
+// ============================================================================
+
+/// New values that make the gossip task run a round right away instead of
+/// waiting out the tick.
+pub const BACKLOG_WAKE: usize = 32;
+
+/// Rounds in a row with nothing to send double the wait, up to this many
+/// times (8 ticks).
+const MAX_BACKOFF_SHIFT: u32 = 3;
+
+/// Sets the pace of a node's gossip task in `--adaptive-gossip` mode: one
+/// tick between rounds while values keep arriving, a growing wait while
+/// there is nothing to send, and an early round as soon as a backlog builds
+/// up or a value arrives during a backed-off wait.
+#[derive(Debug, Default)]
+pub struct GossipPacer {
+    wake: Arc<Notify>,
+    pending: AtomicUsize,
+    idle_rounds: AtomicU32,
+}
+
+impl GossipPacer {
+    /// Notified whenever the gossip task should stop waiting.
+    pub fn wake(&self) -> Arc<Notify> {
+        self.wake.clone()
+    }
+
+    /// Records `count` values accepted since the last round.
+    pub fn record_pending(&self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let pending = self.pending.fetch_add(count, Ordering::Relaxed) + count;
+        if pending >= BACKLOG_WAKE || self.idle_rounds.load(Ordering::Relaxed) > 0 {
+            self.wake.notify_one();
+        }
+    }
+
+    /// Wait before the round after one that sent `fanout` gossip messages.
+    pub fn next_delay(&self, tick: Duration, fanout: usize) -> Duration {
+        self.pending.store(0, Ordering::Relaxed);
+        let idle_rounds = if fanout == 0 {
+            let idle_rounds = self.idle_rounds.load(Ordering::Relaxed) + 1;
+            idle_rounds.min(MAX_BACKOFF_SHIFT)
+        } else {
+            0
+        };
+        self.idle_rounds.store(idle_rounds, Ordering::Relaxed);
+        tick * (1 << idle_rounds)
+    }
+}
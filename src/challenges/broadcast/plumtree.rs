@@ -0,0 +1,199 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::challenges::broadcast::gossip::send_gossip;
+use crate::challenges::broadcast::{BroadcastData, ensure_gossip_task, gossip_missing};
+use crate::challenges::cluster::global_cluster;
+use crate::output::Output;
+use crate::{BodyBase, Message, Payload, clock};
+
+// ============================================================================
+// Spanning-Tree Dissemination (Plumtree Mode)
+// ============================================================================
+//
+// In `--plumtree` mode every neighbour starts out eager: gossip rounds push
+// deltas to it as usual. A gossip that brings nothing new shows its edge is
+// redundant, so the receiver marks the sender lazy and answers with a
+// `gossip_ok` carrying `prune`, which makes the sender do the same. What is
+// left of the eager edges is a spanning tree. Lazy neighbours are only sent
+// `ihave` announcements of new values; a node that hears of a value and
+// doesn't receive it within `GRAFT_TIMEOUT_TICKS` sends a `graft`, which
+// makes that edge eager again and pulls the values through it.
+
+/// Gossip ticks to wait for an announced value before grafting.
+pub const GRAFT_TIMEOUT_TICKS: u32 = 4;
+
+#[derive(Debug, Default)]
+pub struct PlumtreeState {
+    /// Neighbours that only get announcements. Every other one is eager.
+    pub lazy: HashSet<String>,
+    /// Announced values not received yet: who announced each, and when.
+    pub missing: HashMap<u64, (String, Instant)>,
+}
+
+impl PlumtreeState {
+    pub fn is_eager(&self, peer: &str) -> bool {
+        !self.lazy.contains(peer)
+    }
+
+    pub fn prune(&mut self, peer: &str) {
+        self.lazy.insert(peer.to_string());
+    }
+
+    pub fn graft(&mut self, peer: &str) {
+        self.lazy.remove(peer);
+    }
+
+    /// Values announced by `peer` that we don't hold.
+    fn announced(&mut self, peer: &str, values: impl IntoIterator<Item = u64>) {
+        let now = clock::now();
+        for value in values {
+            self.missing
+                .entry(value)
+                .or_insert_with(|| (peer.to_string(), now));
+        }
+    }
+
+    /// Takes the missing values announced at least `timeout` ago, grouped by
+    /// the peer that announced them. Values received since are dropped.
+    fn overdue(
+        &mut self,
+        timeout: Duration,
+        held: impl Fn(u64) -> bool,
+    ) -> HashMap<String, HashSet<u64>> {
+        let mut overdue: HashMap<String, HashSet<u64>> = HashMap::new();
+        self.missing.retain(|value, (peer, since)| {
+            if held(*value) {
+                return false;
+            }
+            if clock::elapsed(*since) < timeout {
+                return true;
+            }
+            overdue.entry(peer.clone()).or_default().insert(*value);
+            false
+        });
+        overdue
+    }
+}
+
+/// Body of `ihave` and `graft`, the one-way messages of the lazy side.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlumtreeBody {
+    #[serde(flatten)]
+    pub base: BodyBase,
+
+    pub values: HashSet<u64>,
+}
+
+/// The lazy side of one gossip round: `ihave` to every lazy neighbour with
+/// values added since its last announcement, and a `graft` for every value
+/// announced too long ago that still hasn't arrived.
+pub fn round_messages(node_id: &str) -> Vec<Message<Payload>> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    if !cluster.config.plumtree {
+        return Vec::new();
+    }
+    let Some(node) = cluster.get_node(node_id) else {
+        return Vec::new();
+    };
+    let mut broadcast_data = node.broadcast_data.lock();
+    let Some(broadcast_data) = broadcast_data.as_mut() else {
+        return Vec::new();
+    };
+
+    let mut messages = Vec::new();
+    for peer in node.peers.iter().filter(|peer| **peer != node.id) {
+        if broadcast_data.plumtree.is_eager(peer) {
+            continue;
+        }
+        let values = broadcast_data.take_announcement(peer);
+        if !values.is_empty() {
+            messages.push(message(&node.id, peer, Payload::IHave, values));
+        }
+    }
+
+    let timeout = cluster.config.gossip_tick * GRAFT_TIMEOUT_TICKS;
+    let overdue = {
+        let data = &broadcast_data.data;
+        broadcast_data
+            .plumtree
+            .overdue(timeout, |value| data.values().contains(value))
+    };
+    for (peer, values) in overdue {
+        broadcast_data.plumtree.graft(&peer);
+        messages.push(message(&node.id, &peer, Payload::Graft, values));
+    }
+    messages
+}
+
+fn message(
+    src: &str,
+    dest: &str,
+    payload: fn(PlumtreeBody) -> Payload,
+    values: HashSet<u64>,
+) -> Message<Payload> {
+    Message {
+        src: src.to_string(),
+        dest: dest.to_string(),
+        body: payload(PlumtreeBody {
+            base: BodyBase::default(),
+            values,
+        }),
+    }
+}
+
+/// Notes which announced values we still lack, to graft them if they don't
+/// arrive through the tree in time.
+pub async fn ihave(msg: Message<PlumtreeBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+    {
+        let mut broadcast_data = node.broadcast_data.lock();
+        let broadcast_data = broadcast_data.get_or_insert_with(BroadcastData::new);
+        let missing: Vec<u64> = msg
+            .body
+            .values
+            .iter()
+            .filter(|value| !broadcast_data.contains(**value))
+            .copied()
+            .collect();
+        broadcast_data.record_ack(&msg.src, &msg.body.values);
+        broadcast_data.plumtree.announced(&msg.src, missing);
+    }
+    // The grafts go out from the gossip task.
+    ensure_gossip_task(node, &cluster, output);
+    Ok(())
+}
+
+/// Makes the sender an eager neighbour again and sends it the values it
+/// asked for.
+pub async fn graft(msg: Message<PlumtreeBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+    let gossip = {
+        let mut broadcast_data = node.broadcast_data.lock();
+        let Some(broadcast_data) = broadcast_data.as_mut() else {
+            return Ok(());
+        };
+        broadcast_data.plumtree.graft(&msg.src);
+        let held: HashSet<u64> = msg
+            .body
+            .values
+            .iter()
+            .filter(|value| broadcast_data.contains(**value))
+            .copied()
+            .collect();
+        gossip_missing(node, broadcast_data, &msg.src, held)
+    };
+    if let Some(gossip) = gossip {
+        send_gossip(gossip, output);
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::challenges::broadcast::{BroadcastData, ensure_gossip_task};
+use crate::challenges::cluster::global_cluster;
+use crate::challenges::node::MsgIdSpace;
+use crate::crdt::{Crdt, ORSet, Tag};
+use crate::output::Output;
+use crate::{BodyBase, ErrorCode, Message, Payload, reply_error, send};
+
+// ============================================================================
+// Broadcast With Retract (OR-Set Mode)
+// ============================================================================
+
+/// Every this many gossip rounds the OR-Set state is resent to every peer even
+/// if it hasn't changed, since sync messages are not acknowledged.
+pub const RESYNC_ROUNDS: u64 = 20;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetractBody {
+    #[serde(flatten)]
+    pub base: BodyBase,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OrSetSyncBody {
+    #[serde(flatten)]
+    pub base: BodyBase,
+
+    pub state: ORSet,
+}
+
+/// Removes a value cluster-wide. Only the adds this node has observed are
+/// cancelled, so a concurrent broadcast of the same value elsewhere survives.
+pub async fn retract(msg: Message<RetractBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    if !cluster.config.or_set {
+        drop(cluster);
+        let text = "retract requires --or-set";
+        let code = ErrorCode::NotSupported;
+        return reply_error(msg.dest, msg.src, msg.body.base.msg_id, code, text, output);
+    }
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+
+    if let Some(value) = msg.body.message {
+        let mut broadcast_data = node.broadcast_data.lock();
+        let broadcast_data = broadcast_data.get_or_insert_with(BroadcastData::new);
+        broadcast_data.or_set.remove(value, &node.id);
+    }
+    ensure_gossip_task(node, &cluster, output);
+
+    let response = Message {
+        src: node.id.clone(),
+        dest: msg.src,
+        body: Payload::RetractOk(RetractBody {
+            base: BodyBase {
+                msg_id: Some(node.get_next_id(MsgIdSpace::Client)),
+                in_reply_to: msg.body.base.msg_id,
+            },
+            message: None,
+        }),
+    };
+    send(&response, output)
+}
+
+/// Merges a peer's OR-Set state, then drops the tombstones every member of
+/// the cluster is now known to have seen. Sync messages are one-way and carry
+/// no msg_id.
+pub async fn or_set_sync(msg: Message<OrSetSyncBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+
+    let changed = {
+        let mut broadcast_data = node.broadcast_data.lock();
+        let or_set = &mut broadcast_data.get_or_insert_with(BroadcastData::new).or_set;
+        let changed = Crdt::merge(or_set, &msg.body.state, &node.id, &msg.src);
+        if changed {
+            or_set.collect_garbage(&node.node_ids);
+        }
+        changed
+    };
+    if changed {
+        ensure_gossip_task(node, &cluster, output);
+    }
+    Ok(())
+}
+
+/// Records a broadcast value in the OR-Set under a fresh tag from this node.
+pub fn add(broadcast_data: &mut BroadcastData, node_id: &str, seq: u64, value: u64) {
+    let tag = Tag {
+        node: node_id.to_string(),
+        seq,
+    };
+    broadcast_data.or_set.add(value, tag);
+}
+
+/// OR-Set sync messages for one gossip round: the full state goes to every
+/// peer that hasn't been sent the current version, and to all of them every
+/// `RESYNC_ROUNDS` rounds.
+pub fn sync_messages(node_id: &str, round: u64) -> Vec<Message<Payload>> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let Some(node) = cluster.get_node(node_id) else {
+        return Vec::new();
+    };
+    let broadcast_data = node.broadcast_data.lock();
+    let Some(broadcast_data) = broadcast_data.as_ref() else {
+        return Vec::new();
+    };
+
+    broadcast_data.or_set_sync.messages(
+        &broadcast_data.or_set,
+        &node.id,
+        &node.peers,
+        round,
+        |state| {
+            Payload::OrSetSync(OrSetSyncBody {
+                base: BodyBase::default(),
+                state,
+            })
+        },
+    )
+}
@@ -0,0 +1,96 @@
+use std::collections::{HashMap, VecDeque};
+
+use clap::ValueEnum;
+use rand::seq::SliceRandom;
+
+use crate::challenges::node::Node;
+use crate::rpc;
+
+// ============================================================================
+// Gossip Target Selection
+// ============================================================================
+
+pub const DEFAULT_GOSSIP_FANOUT: usize = 3;
+
+/// How a gossip round picks which nodes to send deltas to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TargetStrategy {
+    /// Every topology neighbour, every round.
+    #[default]
+    All,
+    /// `fanout` neighbours per round, rotating through the list.
+    RoundRobin,
+    /// `fanout` neighbours per round, chosen at random.
+    RandomK,
+    /// The `fanout` nodes farthest away in the topology graph that still miss
+    /// values, so distant parts of the cluster are seeded first.
+    FarthestFirst,
+    /// The `fanout` neighbours with the lowest measured gossip round-trip
+    /// time. Neighbours not measured yet come first so every one gets timed.
+    LowestLatency,
+}
+
+impl TargetStrategy {
+    /// Nodes to offer this round's gossip to, most preferred first. The round
+    /// sends to the first `limit` of them that actually have a delta.
+    pub fn candidates(self, node: &Node, round: u64) -> Vec<String> {
+        let mut peers: Vec<String> = node
+            .peers
+            .iter()
+            .filter(|peer| **peer != node.id)
+            .cloned()
+            .collect();
+
+        match self {
+            TargetStrategy::All => {}
+            TargetStrategy::RoundRobin => {
+                if !peers.is_empty() {
+                    let start = (round % peers.len() as u64) as usize;
+                    peers.rotate_left(start);
+                }
+            }
+            TargetStrategy::RandomK => peers.shuffle(&mut rand::rng()),
+            TargetStrategy::FarthestFirst if !node.distances.is_empty() => {
+                let mut by_distance: Vec<(&String, &usize)> = node
+                    .distances
+                    .iter()
+                    .filter(|(id, _)| **id != node.id)
+                    .collect();
+                by_distance.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+                peers = by_distance.into_iter().map(|(id, _)| id.clone()).collect();
+            }
+            // No topology yet, so there are no distances to order by.
+            TargetStrategy::FarthestFirst => {}
+            TargetStrategy::LowestLatency => {
+                peers.sort_by_cached_key(|peer| rpc::round_trip(peer).unwrap_or_default());
+            }
+        }
+        peers
+    }
+
+    /// Maximum number of gossip messages per round.
+    pub fn limit(self, fanout: usize) -> usize {
+        match self {
+            TargetStrategy::All => usize::MAX,
+            _ => fanout.max(1),
+        }
+    }
+}
+
+/// Hop distance from `start` to every node reachable in `graph`.
+pub fn hop_distances(graph: &HashMap<String, Vec<String>>, start: &str) -> HashMap<String, usize> {
+    let mut distances = HashMap::from([(start.to_string(), 0)]);
+    let mut queue = VecDeque::from([start.to_string()]);
+
+    while let Some(current) = queue.pop_front() {
+        let depth = distances[&current];
+        for neighbor in graph.get(&current).into_iter().flatten() {
+            if !distances.contains_key(neighbor) {
+                distances.insert(neighbor.clone(), depth + 1);
+                queue.push_back(neighbor.clone());
+            }
+        }
+    }
+
+    distances
+}
@@ -1,18 +1,29 @@
 use std::collections::HashMap;
-use std::sync::{OnceLock, RwLock};
+use std::path::PathBuf;
+use std::sync::{LockResult, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockResult};
+use std::time::Duration;
 
+use super::broadcast::gossip::{DEFAULT_GOSSIP_RETRY_MAX_MS, DEFAULT_GOSSIP_RETRY_MS};
+use super::broadcast::targets::{DEFAULT_GOSSIP_FANOUT, TargetStrategy};
+use super::generate::snowflake::IdStrategy;
 use super::node::Node;
+use crate::cli::Cli;
+use crate::{clock, metrics, timing};
+
+pub const DEFAULT_GOSSIP_TICK_MS: u64 = 50;
 
 pub struct Cluster {
     pub nodes: HashMap<String, Node>,
-    pub is_topology_done: bool
+    pub is_topology_done: bool,
+    pub config: Config,
 }
 
 impl Cluster {
     pub fn new() -> Self {
         Self {
             nodes: HashMap::new(),
-            is_topology_done: false
+            is_topology_done: false,
+            config: Config::default(),
         }
     }
 
@@ -21,13 +32,165 @@ impl Cluster {
         self.nodes.insert(id, node);
     }
 
+    pub fn get_node(&self, id: &str) -> Option<&Node> {
+        self.nodes.get(id)
+    }
+
     pub fn get_node_mut(&mut self, id: &str) -> Option<&mut Node> {
         self.nodes.get_mut(id)
     }
 }
 
-static CLUSTER: OnceLock<RwLock<Cluster>> = OnceLock::new();
+/// How the node runs its workloads, fixed at startup from the command line
+/// and `VORTEX_*` environment variables.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Ignore every topology message after the first one.
+    pub freeze_topology: bool,
+    /// How often gossip tasks flush accumulated values to peers.
+    pub gossip_tick: Duration,
+    /// Gossip right away when a backlog builds up and back off while idle,
+    /// instead of on every tick.
+    pub adaptive_gossip: bool,
+    /// How each gossip round picks its targets.
+    pub gossip_targets: TargetStrategy,
+    /// Targets per round for every strategy except `all`.
+    pub gossip_fanout: usize,
+    /// Wait for a gossip_ok before the first retransmission; doubled on
+    /// every retry up to `gossip_retry_max`.
+    pub gossip_retry: Duration,
+    pub gossip_retry_max: Duration,
+    /// Push gossip eagerly along a spanning tree only and announce values to
+    /// the other neighbours lazily.
+    pub plumtree: bool,
+    /// Probe the other members SWIM-style and stop gossiping to dead ones.
+    pub swim: bool,
+    /// Gossip rounds between digest exchanges with every peer; 0 disables
+    /// anti-entropy.
+    pub anti_entropy_rounds: u64,
+    /// Unacknowledged gossip messages allowed per peer before new values wait.
+    pub max_in_flight: usize,
+    /// Track broadcast values in an OR-Set so they can be retracted.
+    pub or_set: bool,
+    /// Hold values back from reads until their causal predecessors arrive.
+    pub causal: bool,
+    /// Number accepted broadcast values per node.
+    pub sequence: bool,
+    /// How `generate` makes unique ids.
+    pub id_strategy: IdStrategy,
+    /// Directory where snowflake generators persist their progress.
+    pub id_state: Option<PathBuf>,
+    /// Directory holding each node's write-ahead log.
+    pub wal_dir: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            freeze_topology: false,
+            gossip_tick: Duration::from_millis(DEFAULT_GOSSIP_TICK_MS),
+            adaptive_gossip: false,
+            gossip_targets: TargetStrategy::default(),
+            gossip_fanout: DEFAULT_GOSSIP_FANOUT,
+            gossip_retry: Duration::from_millis(DEFAULT_GOSSIP_RETRY_MS),
+            gossip_retry_max: Duration::from_millis(DEFAULT_GOSSIP_RETRY_MAX_MS),
+            plumtree: false,
+            swim: false,
+            anti_entropy_rounds: 0,
+            max_in_flight: usize::MAX,
+            or_set: false,
+            causal: false,
+            sequence: false,
+            id_strategy: IdStrategy::default(),
+            id_state: None,
+            wal_dir: None,
+        }
+    }
+}
+
+impl From<&Cli> for Config {
+    fn from(cli: &Cli) -> Self {
+        let gossip_retry_ms = cli.gossip_retry_ms.max(1);
+        Self {
+            freeze_topology: cli.freeze_topology,
+            gossip_tick: Duration::from_millis(cli.gossip_tick_ms),
+            adaptive_gossip: cli.adaptive_gossip,
+            gossip_targets: cli.gossip_targets,
+            gossip_fanout: cli.gossip_fanout,
+            gossip_retry: Duration::from_millis(gossip_retry_ms),
+            gossip_retry_max: Duration::from_millis(cli.gossip_retry_max_ms.max(gossip_retry_ms)),
+            plumtree: cli.plumtree,
+            swim: cli.swim,
+            anti_entropy_rounds: cli.anti_entropy_rounds,
+            max_in_flight: cli.max_in_flight.map_or(usize::MAX, |max| max.max(1)),
+            or_set: cli.or_set,
+            causal: cli.causal,
+            sequence: cli.sequence,
+            id_strategy: cli.id_strategy,
+            id_state: cli.id_state.clone(),
+            wal_dir: cli.wal.clone(),
+        }
+    }
+}
+
+/// The cluster's lock. Same interface as the `RwLock` it wraps, but blocking
+/// acquisitions report how long they waited to `timing`.
+pub struct ClusterLock(RwLock<Cluster>);
+
+impl ClusterLock {
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, Cluster>> {
+        let started = clock::now();
+        let guard = self.0.read();
+        metrics::record_lock_wait(clock::elapsed(started));
+        timing::record_lock_wait(started);
+        guard
+    }
+
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, Cluster>> {
+        let started = clock::now();
+        let guard = self.0.write();
+        metrics::record_lock_wait(clock::elapsed(started));
+        timing::record_lock_wait(started);
+        guard
+    }
+
+    pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<'_, Cluster>> {
+        self.0.try_write()
+    }
+}
+
+static CLUSTER: OnceLock<ClusterLock> = OnceLock::new();
+
+pub fn global_cluster() -> &'static ClusterLock {
+    CLUSTER.get_or_init(|| ClusterLock(RwLock::new(Cluster::new())))
+}
 
-pub fn global_cluster() -> &'static RwLock<Cluster> {
-    CLUSTER.get_or_init(|| RwLock::new(Cluster::new()))
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn config_takes_the_command_line_settings() {
+        let cli = Cli::parse_from([
+            "vortex",
+            "--gossip-tick-ms",
+            "20",
+            "--gossip-retry-ms",
+            "300",
+            "--gossip-retry-max-ms",
+            "100",
+            "--max-in-flight",
+            "0",
+            "broadcast",
+        ]);
+        let config = Config::from(&cli);
+        assert_eq!(config.gossip_tick, Duration::from_millis(20));
+        // The backoff cap never undercuts the first retry, and at least one
+        // gossip message may always be in flight.
+        assert_eq!(config.gossip_retry_max, config.gossip_retry);
+        assert_eq!(config.max_in_flight, 1);
+        assert!(!config.plumtree);
+    }
 }
@@ -0,0 +1,220 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::challenges::broadcast::ReadBody;
+use crate::challenges::broadcast::retract::RESYNC_ROUNDS;
+use crate::challenges::cluster::global_cluster;
+use crate::challenges::node::{MsgIdSpace, Node};
+use crate::crdt::{Crdt, ORSwot, Replicator, Tag};
+use crate::handler::{Registry, route};
+use crate::output::Output;
+use crate::scheduler;
+use crate::{BodyBase, Message, Payload, send};
+
+// ============================================================================
+// Message Body Types
+// ============================================================================
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SetBody {
+    #[serde(flatten)]
+    pub base: BodyBase,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub element: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SetSyncBody {
+    #[serde(flatten)]
+    pub base: BodyBase,
+
+    pub state: ORSwot,
+}
+
+// ============================================================================
+// OR-Set Replica
+// ============================================================================
+
+/// This node's replica of the set and the task gossiping it to peers.
+#[derive(Debug)]
+pub struct SetData {
+    pub set: ORSwot,
+    pub sync: Replicator,
+    sync_task: Option<JoinHandle<()>>,
+}
+
+impl SetData {
+    pub fn new() -> Self {
+        Self {
+            set: ORSwot::new(),
+            sync: Replicator::new(RESYNC_ROUNDS),
+            sync_task: None,
+        }
+    }
+}
+
+impl Default for SetData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The node's replica, starting its sync task on first use.
+fn set_data<'a>(
+    data: &'a mut Option<SetData>,
+    node_id: &str,
+    tick: Duration,
+    output: &Output,
+) -> &'a mut SetData {
+    let data = data.get_or_insert_with(SetData::new);
+    if data.sync_task.is_none() {
+        data.sync_task = Some(spawn_sync_task(node_id.to_string(), tick, output.clone()));
+    }
+    data
+}
+
+/// Starts the task that sends each peer the set's state every `tick` it
+/// has changed, and to all of them every `RESYNC_ROUNDS` ticks.
+fn spawn_sync_task(node_id: String, tick: Duration, output: Output) -> JoinHandle<()> {
+    scheduler::every("or_set_sync_timer", tick, move |round| {
+        for message in sync_messages(&node_id, round) {
+            let _ = send(&message, &output);
+        }
+        async {}
+    })
+}
+
+fn sync_messages(node_id: &str, round: u64) -> Vec<Message<Payload>> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let Some(node) = cluster.get_node(node_id) else {
+        return Vec::new();
+    };
+    let data = node.set_data.lock();
+    let Some(data) = data.as_ref() else {
+        return Vec::new();
+    };
+
+    data.sync
+        .messages(&data.set, &node.id, &node.peers, round, |state| {
+            Payload::SetSync(SetSyncBody {
+                base: BodyBase::default(),
+                state,
+            })
+        })
+}
+
+// ============================================================================
+// Handlers
+// ============================================================================
+
+pub fn register(registry: &mut Registry) {
+    registry.register("add", route!(Payload::Add => add));
+    registry.register("remove", route!(Payload::Remove => remove));
+    registry.register("read", route!(Payload::Read => read));
+    registry.register("set_sync", route!(Payload::SetSync => set_sync));
+}
+
+/// Adds an element under a fresh tag from this node.
+pub async fn add(msg: Message<SetBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+
+    if let Some(element) = msg.body.element {
+        let tag = Tag {
+            node: node.id.clone(),
+            seq: node.get_next_id(MsgIdSpace::OrSetTag),
+        };
+        let mut data = node.set_data.lock();
+        set_data(&mut data, &node.id, cluster.config.gossip_tick, output)
+            .set
+            .add(element, tag);
+    }
+    let reply = ok_reply(node, msg, Payload::AddOk);
+    send(&reply, output)
+}
+
+/// Removes the adds of an element this node has observed; a concurrent add
+/// elsewhere survives.
+pub async fn remove(msg: Message<SetBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+
+    if let Some(element) = msg.body.element {
+        let mut data = node.set_data.lock();
+        set_data(&mut data, &node.id, cluster.config.gossip_tick, output)
+            .set
+            .remove(element);
+    }
+    let reply = ok_reply(node, msg, Payload::RemoveOk);
+    send(&reply, output)
+}
+
+/// Replies with the elements currently present, in ascending order.
+pub async fn read(msg: Message<ReadBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+
+    let mut elements: Vec<u64> = node
+        .set_data
+        .lock()
+        .as_ref()
+        .map(|data| data.set.value().into_iter().collect())
+        .unwrap_or_default();
+    elements.sort_unstable();
+
+    let reply = Message {
+        src: node.id.clone(),
+        dest: msg.src,
+        body: Payload::ReadOk(ReadBody {
+            base: BodyBase {
+                msg_id: Some(node.get_next_id(MsgIdSpace::Client)),
+                in_reply_to: msg.body.base.msg_id,
+            },
+            value: Some(serde_json::json!(elements)),
+            ..Default::default()
+        }),
+    };
+    send(&reply, output)
+}
+
+/// Merges a peer's state. Sync messages are one-way and carry no msg_id.
+pub async fn set_sync(msg: Message<SetSyncBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+
+    let mut data = node.set_data.lock();
+    set_data(&mut data, &node.id, cluster.config.gossip_tick, output)
+        .set
+        .merge(&msg.body.state);
+    Ok(())
+}
+
+fn ok_reply(
+    node: &Node,
+    msg: Message<SetBody>,
+    variant: fn(SetBody) -> Payload,
+) -> Message<Payload> {
+    Message {
+        src: node.id.clone(),
+        dest: msg.src,
+        body: variant(SetBody {
+            base: BodyBase {
+                msg_id: Some(node.get_next_id(MsgIdSpace::Client)),
+                in_reply_to: msg.body.base.msg_id,
+            },
+            element: None,
+        }),
+    }
+}
@@ -1,8 +1,9 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::{send, BodyBase, Message, challenges::cluster::global_cluster};
-use std::io::Write;
+use crate::{send, BodyBase, Message, Payload, challenges::cluster::global_cluster, output::Output};
+use crate::challenges::node::MsgIdSpace;
+use crate::handler::{Registry, route};
 
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -15,27 +16,30 @@ pub struct EchoBody {
 }
 
 
-pub fn echo(msg: Message<EchoBody>, output: &mut impl Write) -> Result<()> {
+pub async fn echo(msg: Message<EchoBody>, output: &Output) -> Result<()> {
     let node_id = msg.dest.clone();
-    let mut cluster = global_cluster()
-        .write()
+    let cluster = global_cluster()
+        .read()
         .expect("cluster lock poisoned");
     let node = cluster
-        .get_node_mut(&node_id)
+        .get_node(&node_id)
         .context("node not found in cluster")?;
 
     let reply = Message {
         src: node.id.clone(),
         dest: msg.src,
-        body: EchoBody {
+        body: Payload::EchoOk(EchoBody {
             base: BodyBase {
-                typ: "echo_ok".into(),
-                msg_id: Some(node.get_next_id()),
+                msg_id: Some(node.get_next_id(MsgIdSpace::Client)),
                 in_reply_to: msg.body.base.msg_id,
             },
             echo: msg.body.echo,
-        },
+        }),
     };
 
     send(&reply, output)
 }
+
+pub fn register(registry: &mut Registry) {
+    registry.register("echo", route!(Payload::Echo => echo));
+}
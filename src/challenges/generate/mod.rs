@@ -1,36 +1,123 @@
-use crate::{send, BodyBase, Message, challenges::cluster::global_cluster};
+pub mod snowflake;
+
+use crate::{send, BodyBase, Message, Payload, challenges::cluster::global_cluster, output::Output};
+use crate::{ErrorCode, reply_error};
+use crate::challenges::node::{MsgIdSpace, Node};
+use crate::handler::{Registry, route};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::io::Write;
+use serde_json::Value;
+use snowflake::IdStrategy;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GenerateBody {
     #[serde(flatten)]
-    body: BodyBase,
+    pub base: BodyBase,
 
-    id: Option<String>,
+    /// A UUID string, or a number with `--id-strategy snowflake`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
 }
 
-pub fn generate_unique_id(msg: Message<GenerateBody>, output: &mut impl Write) -> Result<()> {
+/// Largest `count` a single `generate_batch` may ask for.
+const MAX_BATCH: usize = 10_000;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenerateBatchBody {
+    #[serde(flatten)]
+    pub base: BodyBase,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ids: Option<Vec<Value>>,
+}
+
+pub async fn generate_unique_id(msg: Message<GenerateBody>, output: &Output) -> Result<()> {
     let node_id = msg.dest.clone();
-    let mut cluster = global_cluster().write().expect("cluster lock poisoned");
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let id_strategy = cluster.config.id_strategy;
     let node = cluster
-        .get_node_mut(&node_id)
+        .get_node(&node_id)
         .context("node not found in cluster")?;
 
-    let unique_id = Uuid::new_v4().to_string();
-    let response: Message<GenerateBody> = Message {
+    let unique_id = match id_strategy {
+        IdStrategy::Uuid => Value::from(Uuid::new_v4().to_string()),
+        IdStrategy::Snowflake => {
+            let mut snowflake = node.snowflake.lock().expect("snowflake poisoned");
+            Value::from(snowflake.next_id(node_index(node))?)
+        }
+    };
+    let response: Message<Payload> = Message {
         src: node.id.clone(),
         dest: msg.src.clone(),
-        body: GenerateBody {
+        body: Payload::GenerateOk(GenerateBody {
             id: Some(unique_id),
-            body: BodyBase {
-                typ: "generate_ok".to_string(),
-                msg_id: Some(node.get_next_id()),
-                in_reply_to: msg.body.body.msg_id,
+            base: BodyBase {
+                msg_id: Some(node.get_next_id(MsgIdSpace::Client)),
+                in_reply_to: msg.body.base.msg_id,
+            },
+        }),
+    };
+    send(&response, output)
+}
+
+/// Replies with `count` unique ids at once, saving a round trip per id.
+pub async fn generate_batch(msg: Message<GenerateBatchBody>, output: &Output) -> Result<()> {
+    let count = msg.body.count.unwrap_or(1);
+    if count > MAX_BATCH {
+        let text = format!("count must be at most {MAX_BATCH}");
+        let code = ErrorCode::MalformedRequest;
+        return reply_error(msg.dest, msg.src, msg.body.base.msg_id, code, text, output);
+    }
+
+    let node_id = msg.dest.clone();
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let id_strategy = cluster.config.id_strategy;
+    let node = cluster
+        .get_node(&node_id)
+        .context("node not found in cluster")?;
+
+    let ids = match id_strategy {
+        IdStrategy::Uuid => (0..count)
+            .map(|_| Value::from(Uuid::new_v4().to_string()))
+            .collect(),
+        IdStrategy::Snowflake => {
+            let index = node_index(node);
+            let mut snowflake = node.snowflake.lock().expect("snowflake poisoned");
+            snowflake
+                .next_ids(index, count)?
+                .into_iter()
+                .map(Value::from)
+                .collect()
+        }
+    };
+    let response: Message<Payload> = Message {
+        src: node.id.clone(),
+        dest: msg.src.clone(),
+        body: Payload::GenerateBatchOk(GenerateBatchBody {
+            base: BodyBase {
+                msg_id: Some(node.get_next_id(MsgIdSpace::Client)),
+                in_reply_to: msg.body.base.msg_id,
             },
-        },
+            count: None,
+            ids: Some(ids),
+        }),
     };
     send(&response, output)
 }
+
+/// The node's position in init's node_ids, which every node agrees on.
+fn node_index(node: &Node) -> u64 {
+    node.node_ids
+        .iter()
+        .position(|id| *id == node.id)
+        .unwrap_or_default() as u64
+}
+
+pub fn register(registry: &mut Registry) {
+    registry.register("generate", route!(Payload::Generate => generate_unique_id));
+    registry.register("generate_batch", route!(Payload::GenerateBatch => generate_batch));
+}
@@ -0,0 +1,200 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+use clap::ValueEnum;
+
+use crate::clock;
+
+// ============================================================================
+// Snowflake IDs
+// ============================================================================
+
+/// Start of the 41-bit millisecond timestamp (2024-01-01T00:00:00Z), good
+/// for about 69 years.
+const EPOCH_MS: u64 = 1_704_067_200_000;
+const NODE_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+const MAX_SEQUENCE: u64 = (1 << SEQUENCE_BITS) - 1;
+const MAX_NODE: u64 = (1 << NODE_BITS) - 1;
+/// How far past the current millisecond a persisted lease reaches, so the
+/// lease file is rewritten about once a second rather than per id.
+const LEASE_MS: u64 = 1_000;
+
+/// How `generate` makes its ids.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum IdStrategy {
+    /// Random UUIDv4 strings.
+    #[default]
+    Uuid,
+    /// Sortable u64s: milliseconds since 2024, node index and a per-node
+    /// sequence number.
+    Snowflake,
+}
+
+/// Per-node state of the snowflake generator.
+#[derive(Debug, Default)]
+pub struct Snowflake {
+    last_ms: u64,
+    sequence: u64,
+    /// File holding a timestamp no id has reached yet, with `--id-state`.
+    lease_path: Option<PathBuf>,
+    lease_until: u64,
+}
+
+impl Snowflake {
+    /// A generator that persists its progress to `path`. Ids issued after a
+    /// restart start from the persisted lease, so they can't repeat ids from
+    /// before it even if the clock has stepped back since.
+    pub fn with_lease(path: PathBuf) -> anyhow::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let lease_until = match fs::read_to_string(&path) {
+            Ok(text) => text
+                .trim()
+                .parse()
+                .with_context(|| format!("corrupt id lease {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            last_ms: lease_until,
+            sequence: 0,
+            lease_path: Some(path),
+            lease_until,
+        })
+    }
+
+    /// Composes `timestamp << 22 | node_index << 12 | sequence`. When a
+    /// millisecond's 4096 sequence numbers run out the timestamp moves on to
+    /// the next millisecond, and a clock that steps back is treated as not
+    /// having moved.
+    pub fn next_id(&mut self, node_index: u64) -> anyhow::Result<u64> {
+        let mut now = elapsed_ms().max(self.last_ms);
+        if now == self.last_ms {
+            if self.sequence == MAX_SEQUENCE {
+                now += 1;
+                self.sequence = 0;
+            } else {
+                self.sequence += 1;
+            }
+        } else {
+            self.sequence = 0;
+        }
+        self.renew_lease(now)?;
+        self.last_ms = now;
+        Ok(now << (NODE_BITS + SEQUENCE_BITS)
+            | (node_index & MAX_NODE) << SEQUENCE_BITS
+            | self.sequence)
+    }
+
+    /// Persists a lease past `now` before any id at `now` is handed out.
+    /// Written to a temporary file and renamed so a crash mid-write can't
+    /// leave a truncated lease behind.
+    fn renew_lease(&mut self, now: u64) -> anyhow::Result<()> {
+        let Some(path) = &self.lease_path else {
+            return Ok(());
+        };
+        if now < self.lease_until {
+            return Ok(());
+        }
+        let lease_until = now + LEASE_MS;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, lease_until.to_string())?;
+        fs::rename(&tmp, path)?;
+        self.lease_until = lease_until;
+        Ok(())
+    }
+
+    /// `count` ids, claiming a run of the current millisecond's sequence
+    /// numbers at a time rather than one id per call.
+    pub fn next_ids(&mut self, node_index: u64, count: usize) -> anyhow::Result<Vec<u64>> {
+        let mut ids = Vec::with_capacity(count);
+        while ids.len() < count {
+            let first = self.next_id(node_index)?;
+            let run = ((count - ids.len() - 1) as u64).min(MAX_SEQUENCE - self.sequence);
+            self.sequence += run;
+            ids.extend(first..=first + run);
+        }
+        Ok(ids)
+    }
+}
+
+fn elapsed_ms() -> u64 {
+    clock::wall_clock_ms().saturating_sub(EPOCH_MS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lease_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vortex-lease-{}", std::process::id()));
+        let path = dir.join(format!("{name}.lease"));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn timestamp(id: u64) -> u64 {
+        id >> (NODE_BITS + SEQUENCE_BITS)
+    }
+
+    #[test]
+    fn ids_carry_the_node_index_and_increase() {
+        let mut snowflake = Snowflake::default();
+        let ids = snowflake.next_ids(5, 10_000).unwrap();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.iter().all(|id| (id >> SEQUENCE_BITS) & MAX_NODE == 5));
+    }
+
+    #[test]
+    fn a_clock_that_steps_back_is_treated_as_stopped() {
+        let ahead = elapsed_ms() + 60_000;
+        let mut snowflake = Snowflake {
+            last_ms: ahead,
+            sequence: 0,
+            ..Default::default()
+        };
+        let first = snowflake.next_id(1).unwrap();
+        let second = snowflake.next_id(1).unwrap();
+        assert_eq!(timestamp(first), ahead);
+        assert!(second > first);
+    }
+
+    #[test]
+    fn lease_is_renewed_ahead_of_issued_ids() {
+        let path = lease_path("renew");
+        let mut snowflake = Snowflake::with_lease(path.clone()).unwrap();
+        let id = snowflake.next_id(0).unwrap();
+        let lease: u64 = fs::read_to_string(&path).unwrap().parse().unwrap();
+        assert_eq!(lease, timestamp(id) + LEASE_MS);
+
+        // Ids within the lease don't rewrite it.
+        snowflake.next_id(0).unwrap();
+        let unchanged: u64 = fs::read_to_string(&path).unwrap().parse().unwrap();
+        assert_eq!(unchanged, lease);
+    }
+
+    #[test]
+    fn a_restart_resumes_past_the_lease() {
+        let path = lease_path("restart");
+        // A lease left by a run whose clock was an hour ahead of this one.
+        let lease = elapsed_ms() + 3_600_000;
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, lease.to_string()).unwrap();
+
+        let mut snowflake = Snowflake::with_lease(path.clone()).unwrap();
+        let id = snowflake.next_id(0).unwrap();
+        assert!(timestamp(id) >= lease);
+    }
+
+    #[test]
+    fn a_corrupt_lease_is_an_error() {
+        let path = lease_path("corrupt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "not a number").unwrap();
+        assert!(Snowflake::with_lease(path).is_err());
+    }
+}
@@ -1,11 +1,17 @@
-use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
 
-use crate::challenges::{cluster::global_cluster, node::Node};
+use crate::challenges::{
+    broadcast, cluster::global_cluster, generate::snowflake::Snowflake, membership, node::Node,
+};
+use crate::storage::wal::Wal;
+use crate::{logging, scheduler};
 
-use super::super::{BodyBase, Message, send};
-use anyhow::Result;
+use super::super::{BodyBase, Message, Payload, send};
+use crate::handler::{Registry, route};
+use crate::output::Output;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::io::Write;
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct InitBody {
@@ -19,35 +25,86 @@ pub struct InitBody {
     pub node_ids: Option<Vec<String>>,
 }
 
+/// How often a node with a write-ahead log checks whether to checkpoint it.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Records appended since the last checkpoint, in bytes, that make the next
+/// check rewrite the log.
+const CHECKPOINT_AFTER_BYTES: u64 = 64 * 1024;
+
 /// Replies to an init message with init_ok.
-pub fn init(msg: Message<InitBody>, output: &mut impl Write) -> Result<()> {
-    let node_id = msg.body.node_id.clone().unwrap();
-    let peers = msg.body.node_ids.clone().unwrap();
-    let node: Node = Node {
-        id: node_id.clone(),
-        peers,
-        next_msg_id: 0,
-        broadcast_data: None,
-        gossip_thread: None,
-    };
+pub async fn init(msg: Message<InitBody>, output: &Output) -> Result<()> {
+    let node_id = msg.body.node_id.clone().context("init without node_id")?;
+    let peers = msg.body.node_ids.clone().context("init without node_ids")?;
 
     let cluster = global_cluster();
     let mut cluster = cluster.write().expect("cluster lock poisoned");
+    let snowflake = match &cluster.config.id_state {
+        Some(dir) => Snowflake::with_lease(dir.join(format!("{node_id}.lease")))?,
+        None => Snowflake::default(),
+    };
+    let (wal, logged) = match &cluster.config.wal_dir {
+        Some(dir) => {
+            let (wal, logged) = Wal::open(&dir.join(format!("{node_id}.wal")))?;
+            (Some(Mutex::new(wal)), logged)
+        }
+        None => (None, Vec::new()),
+    };
+    let mut node = Node {
+        snowflake: Mutex::new(snowflake),
+        ..Node::new(node_id.clone(), peers)
+    };
+    node.replay(logged)?;
+    if wal.is_some() {
+        node.wal = wal;
+        spawn_checkpoints(node_id.clone());
+    }
+    broadcast::restore(&node, &cluster, output);
+    if cluster.config.swim {
+        membership::spawn(&node, output);
+    }
     cluster.add_node(node);
 
-    let response: Message<InitBody> = Message {
+    let response: Message<Payload> = Message {
         src: node_id,
         dest: msg.src,
-        body: InitBody {
+        body: Payload::InitOk(InitBody {
             base: BodyBase {
-                typ: String::from_str("init_ok").unwrap(),
                 in_reply_to: msg.body.base.msg_id,
                 msg_id: None,
             },
             node_id: None,
             node_ids: None,
-        },
+        }),
     };
 
     send(&response, output)
 }
+
+pub fn register(registry: &mut Registry) {
+    registry.register("init", route!(Payload::Init => init));
+}
+
+/// Starts a task that replaces the node's write-ahead log with a checkpoint
+/// of its state once enough has been appended, so the log and the time to
+/// replay it after a restart stay bounded.
+fn spawn_checkpoints(node_id: String) {
+    scheduler::every("wal_checkpoint_timer", CHECKPOINT_INTERVAL, move |_| {
+        let cluster = global_cluster().read().expect("cluster lock poisoned");
+        if let Some(node) = cluster.get_node(&node_id)
+            && let Some(wal) = &node.wal
+        {
+            let appended = wal.lock().expect("wal poisoned").since_checkpoint();
+            if appended >= CHECKPOINT_AFTER_BYTES
+                && let Err(err) = node.checkpoint()
+            {
+                logging::event(
+                    logging::Level::Error,
+                    "wal: checkpoint failed",
+                    serde_json::json!({ "node": node_id, "error": format!("{err:#}") }),
+                );
+            }
+        }
+        async {}
+    });
+}
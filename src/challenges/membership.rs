@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use rand::seq::IndexedRandom;
+use serde::{Deserialize, Serialize};
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::challenges::cluster::global_cluster;
+use crate::challenges::node::{MsgIdSpace, Node};
+use crate::handler::{Registry, route};
+use crate::output::Output;
+use crate::{BodyBase, Message, Payload, clock, logging, rpc, scheduler, send};
+
+// ============================================================================
+// Failure Detector (SWIM)
+// ============================================================================
+//
+// In `--swim` mode every node probes one member per protocol period, in
+// turn. A member that doesn't answer the `ping` within `PING_TIMEOUT` is
+// pinged indirectly: `INDIRECT_PROBES` other members are sent a `ping_req`
+// and answer `ping_req_ok` if they reach it. A member no one reached becomes
+// suspect, and a suspect that stays unreachable for `SUSPICION_TIMEOUT` is
+// declared dead. Hearing from a member again makes it alive. The view is
+// local to each node; nothing is disseminated.
+
+pub const PROTOCOL_PERIOD: Duration = Duration::from_millis(500);
+pub const PING_TIMEOUT: Duration = Duration::from_millis(150);
+pub const INDIRECT_PROBES: usize = 3;
+pub const SUSPICION_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Liveness {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+#[derive(Debug)]
+struct PeerHealth {
+    liveness: Liveness,
+    /// When the peer entered its current state.
+    since: Instant,
+}
+
+/// A node's view of which members are alive. Members never probed count as
+/// alive.
+#[derive(Debug, Default)]
+pub struct Membership {
+    peers: Mutex<HashMap<String, PeerHealth>>,
+}
+
+impl Membership {
+    pub fn liveness(&self, peer: &str) -> Liveness {
+        let peers = self.peers.lock().expect("membership poisoned");
+        peers
+            .get(peer)
+            .map_or(Liveness::Alive, |health| health.liveness)
+    }
+
+    pub fn is_dead(&self, peer: &str) -> bool {
+        self.liveness(peer) == Liveness::Dead
+    }
+
+    pub fn record_alive(&self, peer: &str) {
+        self.transition(peer, Liveness::Alive);
+    }
+
+    /// Suspects `peer` after a failed probe, unless it is already suspect or
+    /// dead.
+    fn record_unreachable(&self, peer: &str) {
+        if self.liveness(peer) == Liveness::Alive {
+            self.transition(peer, Liveness::Suspect);
+        }
+    }
+
+    /// Declares dead every member suspected for `SUSPICION_TIMEOUT`.
+    fn expire_suspects(&self) {
+        let expired: Vec<String> = {
+            let peers = self.peers.lock().expect("membership poisoned");
+            peers
+                .iter()
+                .filter(|(_, health)| {
+                    health.liveness == Liveness::Suspect
+                        && clock::elapsed(health.since) >= SUSPICION_TIMEOUT
+                })
+                .map(|(peer, _)| peer.clone())
+                .collect()
+        };
+        for peer in expired {
+            self.transition(&peer, Liveness::Dead);
+        }
+    }
+
+    fn transition(&self, peer: &str, liveness: Liveness) {
+        let mut peers = self.peers.lock().expect("membership poisoned");
+        let previous = peers
+            .get(peer)
+            .map_or(Liveness::Alive, |health| health.liveness);
+        if previous == liveness && peers.contains_key(peer) {
+            return;
+        }
+        peers.insert(
+            peer.to_string(),
+            PeerHealth {
+                liveness,
+                since: clock::now(),
+            },
+        );
+        if previous != liveness {
+            logging::event(
+                logging::Level::Info,
+                "peer_liveness",
+                serde_json::json!({ "peer": peer, "from": previous, "to": liveness }),
+            );
+        }
+    }
+}
+
+/// Whether `node_id`'s failure detector has declared `peer` dead.
+pub fn is_dead(node_id: &str, peer: &str) -> bool {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    cluster
+        .get_node(node_id)
+        .is_some_and(|node| node.membership.is_dead(peer))
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PingBody {
+    #[serde(flatten)]
+    pub base: BodyBase,
+
+    /// The member to probe on the sender's behalf, in a `ping_req`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+// ============================================================================
+// Probe Task
+// ============================================================================
+
+/// Starts probing `node`'s members, one per protocol period.
+pub fn spawn(node: &Node, output: &Output) -> JoinHandle<()> {
+    let node_id = node.id.clone();
+    let output = output.clone();
+    scheduler::every("swim_timer", PROTOCOL_PERIOD, move |round| {
+        let node_id = node_id.clone();
+        let output = output.clone();
+        async move {
+            probe(&node_id, round, &output).await;
+        }
+    })
+}
+
+/// Probes the member whose turn it is this round.
+async fn probe(node_id: &str, round: u64, output: &Output) {
+    let Some((target, helpers)) = pick_target(node_id, round) else {
+        return;
+    };
+    if rpc::call_with_timeout(ping_message(node_id, &target, None), PING_TIMEOUT, output)
+        .await
+        .is_ok()
+    {
+        record(node_id, |membership| membership.record_alive(&target));
+        return;
+    }
+
+    let mut probes = JoinSet::new();
+    for helper in helpers {
+        let request = ping_message(node_id, &helper, Some(target.clone()));
+        let output = output.clone();
+        probes.spawn(async move {
+            rpc::call_with_timeout(request, PROTOCOL_PERIOD - PING_TIMEOUT, &output).await
+        });
+    }
+    while let Some(reply) = probes.join_next().await {
+        if let Ok(Ok(Message {
+            body: Payload::PingReqOk(_),
+            ..
+        })) = reply
+        {
+            record(node_id, |membership| membership.record_alive(&target));
+            return;
+        }
+    }
+    record(node_id, |membership| membership.record_unreachable(&target));
+}
+
+/// This round's target and up to `INDIRECT_PROBES` other members to ask to
+/// probe it. Also retires suspects whose time is up.
+fn pick_target(node_id: &str, round: u64) -> Option<(String, Vec<String>)> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster.get_node(node_id)?;
+    node.membership.expire_suspects();
+
+    let members: Vec<&String> = node.node_ids.iter().filter(|id| **id != node.id).collect();
+    if members.is_empty() {
+        return None;
+    }
+    let target = members[(round % members.len() as u64) as usize].clone();
+    let others: Vec<&String> = members
+        .into_iter()
+        .filter(|id| **id != target && !node.membership.is_dead(id))
+        .collect();
+    let helpers = others
+        .choose_multiple(&mut rand::rng(), INDIRECT_PROBES)
+        .map(|id| (*id).clone())
+        .collect();
+    Some((target, helpers))
+}
+
+fn ping_message(node_id: &str, dest: &str, target: Option<String>) -> Message<Payload> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let msg_id = cluster
+        .get_node(node_id)
+        .map(|node| node.get_next_id(MsgIdSpace::Service));
+    let base = BodyBase {
+        msg_id,
+        in_reply_to: None,
+    };
+    let body = match target {
+        Some(target) => Payload::PingReq(PingBody {
+            base,
+            target: Some(target),
+        }),
+        None => Payload::Ping(PingBody { base, target: None }),
+    };
+    Message {
+        src: node_id.to_string(),
+        dest: dest.to_string(),
+        body,
+    }
+}
+
+fn record(node_id: &str, update: impl FnOnce(&Membership)) {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    if let Some(node) = cluster.get_node(node_id) {
+        update(&node.membership);
+    }
+}
+
+// ============================================================================
+// Message Handlers
+// ============================================================================
+
+pub fn register(registry: &mut Registry) {
+    registry.register("ping", route!(Payload::Ping => ping));
+    registry.register("ping_req", route!(Payload::PingReq => ping_req));
+}
+
+/// Answers a probe.
+pub async fn ping(msg: Message<PingBody>, output: &Output) -> Result<()> {
+    record(&msg.dest, |membership| membership.record_alive(&msg.src));
+    let reply = Message {
+        src: msg.dest,
+        dest: msg.src,
+        body: Payload::PingOk(PingBody {
+            base: BodyBase {
+                msg_id: None,
+                in_reply_to: msg.body.base.msg_id,
+            },
+            target: None,
+        }),
+    };
+    send(&reply, output)
+}
+
+/// Pings `target` on the sender's behalf and answers `ping_req_ok` if it
+/// replies. Nothing is sent back otherwise.
+pub async fn ping_req(msg: Message<PingBody>, output: &Output) -> Result<()> {
+    record(&msg.dest, |membership| membership.record_alive(&msg.src));
+    let Some(target) = msg.body.target.clone() else {
+        return Ok(());
+    };
+    let output = output.clone();
+    // The reply to our ping arrives through the dispatch loop, so wait for
+    // it off the loop.
+    tokio::spawn(async move {
+        let request = ping_message(&msg.dest, &target, None);
+        if rpc::call_with_timeout(request, PING_TIMEOUT, &output)
+            .await
+            .is_err()
+        {
+            return;
+        }
+        record(&msg.dest, |membership| membership.record_alive(&target));
+        let reply = Message {
+            src: msg.dest,
+            dest: msg.src,
+            body: Payload::PingReqOk(PingBody {
+                base: BodyBase {
+                    msg_id: None,
+                    in_reply_to: msg.body.base.msg_id,
+                },
+                target: Some(target),
+            }),
+        };
+        let _ = send(&reply, &output);
+    });
+    Ok(())
+}
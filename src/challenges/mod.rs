@@ -1,6 +1,8 @@
 pub mod init;
 pub mod node;
 pub mod cluster;
+pub mod membership;
+pub mod state;
 
 #[path = "echo/mod.rs"]
 pub mod echo;
@@ -9,4 +11,7 @@ pub mod echo;
 pub mod generate;
 
 #[path ="broadcast/mod.rs"]
-pub mod broadcast;
\ No newline at end of file
+pub mod broadcast;
+
+#[path = "crdt/mod.rs"]
+pub mod crdt;
\ No newline at end of file
@@ -1,21 +1,325 @@
-use std::thread::Thread;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
 
 use crate::challenges::broadcast::BroadcastData;
+use crate::challenges::broadcast::pacing::GossipPacer;
+use crate::challenges::membership::Membership;
+use crate::challenges::crdt::SetData;
+use crate::challenges::generate::snowflake::Snowflake;
+use crate::crdt::{ORSet, ORSwot};
+use crate::storage::wal::Wal;
+use crate::{clock, metrics, strict, timing};
+
+/// Width of each subsystem's msg_id band.
+const MSG_ID_BAND: u64 = 1_000_000_000;
+
+/// Subsystems that allocate msg_ids. Each draws from its own band so a
+/// transcript shows who sent a message: client replies count up from 0,
+/// gossip from 1_000_000_000, service RPCs from 2_000_000_000 and OR-Set add
+/// tags from 3_000_000_000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MsgIdSpace {
+    Client = 0,
+    Gossip = 1,
+    Service = 2,
+    OrSetTag = 3,
+}
+
+impl MsgIdSpace {
+    /// The ids this space may hand out.
+    pub fn band(self) -> Range<u64> {
+        let start = self as u64 * MSG_ID_BAND;
+        start..start + MSG_ID_BAND
+    }
+}
 
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct Node {
     pub id: String,
+    /// Every node in the cluster, as announced by init.
+    pub node_ids: Vec<String>,
+    /// Nodes this node gossips with; narrowed down by topology messages.
     pub peers: Vec<String>,
-    pub next_msg_id: u64,
-    pub broadcast_data: Option<BroadcastData>,
-    pub gossip_thread: Option<Thread>
+    /// Hop distance to every node in the topology graph, used to pick
+    /// far-away gossip targets.
+    pub distances: HashMap<String, usize>,
+    /// Next id within each `MsgIdSpace`, indexed by the space.
+    pub next_msg_ids: [AtomicU64; 4],
+    pub broadcast_data: WorkloadLock<BroadcastData>,
+    pub gossip_task: OnceLock<JoinHandle<()>>,
+    /// Shared with the gossip task to set its pace in `--adaptive-gossip` mode.
+    pub gossip_pacer: Arc<GossipPacer>,
+    pub set_data: WorkloadLock<SetData>,
+    /// Liveness of the other members, in `--swim` mode.
+    pub membership: Membership,
+    pub snowflake: Mutex<Snowflake>,
+    /// Log of accepted state, replayed after a restart, with `--wal`.
+    pub wal: Option<Mutex<Wal>>,
 }
 
 impl Node {
-    pub fn get_next_id(&mut self) -> u64 {
-        let msg_id = self.next_msg_id;
-        self.next_msg_id = self.next_msg_id + 1;
-        return msg_id;
+    /// A node named `id` in a cluster of `node_ids`, gossiping with all of
+    /// them until a topology message says otherwise, with empty workload
+    /// state and no persistence.
+    pub fn new(id: String, node_ids: Vec<String>) -> Self {
+        Self {
+            id,
+            peers: node_ids.clone(),
+            node_ids,
+            distances: HashMap::new(),
+            next_msg_ids: Default::default(),
+            broadcast_data: Default::default(),
+            gossip_task: Default::default(),
+            gossip_pacer: Default::default(),
+            set_data: Default::default(),
+            membership: Default::default(),
+            snowflake: Default::default(),
+            wal: None,
+        }
+    }
+
+    /// Allocates a msg_id from `space`'s band. Atomic so handlers holding
+    /// only a shared cluster lock can still reply.
+    pub fn get_next_id(&self, space: MsgIdSpace) -> u64 {
+        let allocate = || {
+            let offset = self.next_msg_ids[space as usize].fetch_add(1, Ordering::Relaxed);
+            space.band().start + offset
+        };
+        if strict::is_enabled() {
+            return strict::allocate_msg_id(&self.id, space, allocate);
+        }
+        allocate()
+    }
+
+    /// Serializes every workload's state, for the write-ahead log's
+    /// checkpoints, the quiescence checkpoint file or seeding another node.
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.snapshot_of(self.broadcast_data.lock().as_ref())
+    }
+
+    /// [`Node::snapshot`], with the broadcast state already locked.
+    fn snapshot_of(&self, broadcast_data: Option<&BroadcastData>) -> Vec<u8> {
+        serde_json::to_vec(&self.state_of(broadcast_data)).expect("node state serializes")
+    }
+
+    /// Merges a [`Node::snapshot`] taken on this or another node into this
+    /// node's state.
+    pub fn restore(&self, snapshot: &[u8]) -> Result<()> {
+        let state: NodeState = serde_json::from_slice(snapshot).context("decoding snapshot")?;
+        self.merge(&state)
+    }
+
+    /// Copies every workload's state.
+    pub fn state(&self) -> NodeState {
+        self.state_of(self.broadcast_data.lock().as_ref())
+    }
+
+    /// Copies every workload's state, with the broadcast state already locked.
+    fn state_of(&self, broadcast_data: Option<&BroadcastData>) -> NodeState {
+        let mut broadcast: Vec<u64> = broadcast_data
+            .map(|broadcast_data| broadcast_data.clone_data().into_iter().collect())
+            .unwrap_or_default();
+        broadcast.sort_unstable();
+        NodeState {
+            node: self.id.clone(),
+            broadcast,
+            or_set: broadcast_data
+                .map(|broadcast_data| broadcast_data.or_set.clone())
+                .filter(|or_set| !or_set.is_empty()),
+            set: self.set_data.lock().as_ref().map(|data| data.set.clone()),
+        }
+    }
+
+    /// Merges a state taken on this or another node into this node's state.
+    /// Broadcast values it didn't hold are written to its log.
+    pub fn merge(&self, state: &NodeState) -> Result<()> {
+        if !state.broadcast.is_empty() || state.or_set.is_some() {
+            let mut broadcast_data = self.broadcast_data.lock();
+            let broadcast_data = broadcast_data.get_or_insert_with(BroadcastData::new);
+            let new: Vec<u64> = state
+                .broadcast
+                .iter()
+                .filter(|value| !broadcast_data.contains(**value))
+                .copied()
+                .collect();
+            if !new.is_empty() {
+                self.log(&new)?;
+            }
+            self.gossip_pacer.record_pending(new.len());
+            broadcast_data.extend(new.into_iter().collect());
+            if let Some(or_set) = &state.or_set {
+                broadcast_data.or_set.merge(or_set, &self.id, &state.node);
+                self.skip_used_tags(or_set.max_seq(&self.id));
+            }
+        }
+        if let Some(set) = &state.set {
+            let mut data = self.set_data.lock();
+            let data = data.get_or_insert_with(SetData::new);
+            data.set.merge(set);
+            self.skip_used_tags(set.max_seq(&self.id));
+        }
+        Ok(())
+    }
+
+    /// Moves the OR-Set tag counter past `max_seq`, the highest tag of this
+    /// node's in a restored set, so a restarted node never reuses a tag it
+    /// handed out before.
+    fn skip_used_tags(&self, max_seq: Option<u64>) {
+        if let Some(seq) = max_seq {
+            let space = MsgIdSpace::OrSetTag;
+            let next = (seq + 1).saturating_sub(space.band().start);
+            self.next_msg_ids[space as usize].fetch_max(next, Ordering::Relaxed);
+        }
+    }
+
+    /// Rebuilds the state recorded in the node's write-ahead log: lists of
+    /// broadcast values, after the snapshot of the last checkpoint if there
+    /// was one. Runs before the log is attached, so nothing replayed is
+    /// logged again.
+    pub fn replay(&self, records: Vec<Vec<u8>>) -> Result<()> {
+        debug_assert!(self.wal.is_none(), "replaying into a node with a log");
+        for record in records {
+            match serde_json::from_slice::<Vec<u64>>(&record) {
+                Ok(values) => {
+                    let mut broadcast_data = self.broadcast_data.lock();
+                    let broadcast_data = broadcast_data.get_or_insert_with(BroadcastData::new);
+                    broadcast_data.extend(values.into_iter().collect());
+                }
+                Err(_) => self.restore(&record)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the node's write-ahead log with a checkpoint of its state
+    /// followed by anything logged while the snapshot was taken. A no-op for
+    /// a node without a log.
+    pub fn checkpoint(&self) -> Result<()> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+        let (snapshot, covered) = {
+            // Broadcast values are logged with the broadcast state locked, so
+            // holding it keeps the log's length in step with the snapshot.
+            let broadcast_data = self.broadcast_data.lock();
+            let covered = wal.lock().expect("wal poisoned").len()?;
+            (self.snapshot_of(broadcast_data.as_ref()), covered)
+        };
+        wal.lock()
+            .expect("wal poisoned")
+            .checkpoint(&snapshot, covered)
+    }
+
+    /// Appends `record` to the node's write-ahead log, if it keeps one.
+    /// Broadcast values are logged with `broadcast_data` locked, which
+    /// [`Node::checkpoint`] relies on.
+    pub fn log<T: Serialize>(&self, record: &T) -> Result<()> {
+        match &self.wal {
+            Some(wal) => {
+                let record = serde_json::to_vec(record)?;
+                wal.lock().expect("wal poisoned").append(&record)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// One workload's state on a node, behind its own lock. Handlers only need a
+/// shared cluster lock to reach it, so different workloads and the gossip
+/// task don't wait on each other. Always taken after the cluster lock, never
+/// before it.
+#[derive(Debug)]
+pub struct WorkloadLock<T>(Mutex<Option<T>>);
+
+impl<T> Default for WorkloadLock<T> {
+    fn default() -> Self {
+        Self(Mutex::new(None))
+    }
+}
+
+impl<T> WorkloadLock<T> {
+    /// Locks the state, which is `None` until the workload first runs.
+    /// Waiting is reported to `metrics` and `timing` like the cluster lock.
+    pub fn lock(&self) -> MutexGuard<'_, Option<T>> {
+        let started = clock::now();
+        let guard = self.0.lock().expect("workload lock poisoned");
+        metrics::record_lock_wait(clock::elapsed(started));
+        timing::record_lock_wait(started);
+        guard
+    }
+}
+
+/// A node's workload state, as taken by [`Node::snapshot`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NodeState {
+    /// The node the snapshot was taken on.
+    pub node: String,
+    /// Broadcast values held, in ascending order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub broadcast: Vec<u64>,
+    /// The broadcast workload's OR-Set, in `--or-set` mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub or_set: Option<ORSet>,
+    /// The `or-set` workload's replica.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub set: Option<ORSwot>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::Tag;
+
+    fn node(id: &str) -> Node {
+        Node::new(id.to_string(), vec![id.to_string()])
+    }
+
+    #[test]
+    fn snapshot_restores_onto_a_fresh_node() {
+        let original = node("n-snapshot");
+        {
+            let mut broadcast_data = original.broadcast_data.lock();
+            let broadcast_data = broadcast_data.get_or_insert_with(BroadcastData::new);
+            broadcast_data.extend([3, 1, 2].into());
+            let seq = original.get_next_id(MsgIdSpace::OrSetTag);
+            let tag = Tag {
+                node: original.id.clone(),
+                seq,
+            };
+            broadcast_data.or_set.add(9, tag);
+        }
+
+        let restarted = node("n-snapshot");
+        restarted.restore(&original.snapshot()).unwrap();
+        let state = restarted.state();
+        assert_eq!(state.broadcast, [1, 2, 3]);
+        assert_eq!(state.or_set.unwrap().elements(), [9].into());
+        // The restarted node doesn't hand out the restored add's tag again.
+        assert_eq!(
+            restarted.get_next_id(MsgIdSpace::OrSetTag),
+            original.get_next_id(MsgIdSpace::OrSetTag)
+        );
+    }
+
+    #[test]
+    fn replay_applies_values_logged_after_a_checkpoint() {
+        let original = node("n-replay");
+        original
+            .broadcast_data
+            .lock()
+            .get_or_insert_with(BroadcastData::new)
+            .extend([1, 2].into());
+        let records = vec![original.snapshot(), b"[3]".to_vec(), b"[4,5]".to_vec()];
+
+        let restarted = node("n-replay");
+        restarted.replay(records).unwrap();
+        assert_eq!(restarted.state().broadcast, [1, 2, 3, 4, 5]);
     }
 }
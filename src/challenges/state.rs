@@ -0,0 +1,77 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::challenges::cluster::global_cluster;
+use crate::challenges::node::{MsgIdSpace, NodeState};
+use crate::handler::{Registry, route};
+use crate::output::Output;
+use crate::{BodyBase, Message, Payload, send};
+
+// ============================================================================
+// State Snapshots
+// ============================================================================
+
+/// Admin messages carrying a node's workload state: `dump_state_ok` returns
+/// it for debugging, and `restore_state` merges one in, e.g. to seed a node
+/// that joined late with a peer's state.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StateBody {
+    #[serde(flatten)]
+    pub base: BodyBase,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<NodeState>,
+}
+
+pub fn register(registry: &mut Registry) {
+    registry.register("dump_state", route!(Payload::DumpState => dump_state));
+    registry.register(
+        "restore_state",
+        route!(Payload::RestoreState => restore_state),
+    );
+}
+
+/// Replies with a snapshot of the node's state.
+pub async fn dump_state(msg: Message<StateBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+
+    let reply = Message {
+        src: node.id.clone(),
+        dest: msg.src,
+        body: Payload::DumpStateOk(StateBody {
+            base: BodyBase {
+                msg_id: Some(node.get_next_id(MsgIdSpace::Client)),
+                in_reply_to: msg.body.base.msg_id,
+            },
+            state: Some(node.state()),
+        }),
+    };
+    send(&reply, output)
+}
+
+/// Merges the snapshot in the request into the node's state.
+pub async fn restore_state(msg: Message<StateBody>, output: &Output) -> Result<()> {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let node = cluster
+        .get_node(&msg.dest)
+        .context("node not found in cluster")?;
+
+    if let Some(state) = &msg.body.state {
+        node.merge(state)?;
+    }
+    let reply = Message {
+        src: node.id.clone(),
+        dest: msg.src,
+        body: Payload::RestoreStateOk(StateBody {
+            base: BodyBase {
+                msg_id: Some(node.get_next_id(MsgIdSpace::Client)),
+                in_reply_to: msg.body.base.msg_id,
+            },
+            state: None,
+        }),
+    };
+    send(&reply, output)
+}
@@ -0,0 +1,337 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+use tokio::sync::Notify;
+
+// ============================================================================
+// Bounded, Observable Channels
+// ============================================================================
+
+/// What `send` does when the queue already holds `capacity` items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wait for the receiver to make room (`try_send` reports `Full`).
+    Block,
+    /// Discard the oldest queued item to make room.
+    DropOldest,
+    /// Reject the new item.
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The receiver has been dropped.
+    Closed,
+    /// The queue is at capacity and the overflow policy rejected the item.
+    Full,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Closed => f.write_str("channel closed"),
+            SendError::Full => f.write_str("channel full"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+/// Depth gauges for one channel, kept in a process-wide list for as long as
+/// the channel exists so every queue can be reported in one place.
+#[derive(Debug)]
+pub struct QueueStats {
+    pub name: &'static str,
+    pub capacity: usize,
+    depth: AtomicUsize,
+    high_water: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+impl QueueStats {
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    pub fn high_water(&self) -> usize {
+        self.high_water.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+static QUEUES: OnceLock<Mutex<Vec<Weak<QueueStats>>>> = OnceLock::new();
+
+fn queues() -> &'static Mutex<Vec<Weak<QueueStats>>> {
+    QUEUES.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// Adds a new channel's gauges to the list. Short-lived channels, such as the
+/// one per RPC call, are pruned here once both ends are gone, so the list
+/// stays as long as the number of live channels even when nothing reads it.
+fn register(stats: &Arc<QueueStats>) {
+    let mut queues = queues().lock().expect("queue registry poisoned");
+    queues.retain(|queue| queue.strong_count() > 0);
+    queues.push(Arc::downgrade(stats));
+}
+
+/// Gauges of every channel that still exists.
+pub fn all_stats() -> Vec<Arc<QueueStats>> {
+    let queues = queues().lock().expect("queue registry poisoned");
+    queues.iter().filter_map(Weak::upgrade).collect()
+}
+
+struct Shared<T> {
+    queue: Mutex<VecDeque<T>>,
+    overflow: Overflow,
+    stats: Arc<QueueStats>,
+    readable: Notify,
+    writable: Notify,
+    senders: AtomicUsize,
+    closed: AtomicBool,
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Creates a channel holding at most `capacity` items (`usize::MAX` for no
+/// limit) whose depth is reported under `name`.
+pub fn channel<T>(
+    name: &'static str,
+    capacity: usize,
+    overflow: Overflow,
+) -> (Sender<T>, Receiver<T>) {
+    let stats = Arc::new(QueueStats {
+        name,
+        capacity,
+        depth: AtomicUsize::new(0),
+        high_water: AtomicUsize::new(0),
+        dropped: AtomicU64::new(0),
+    });
+    register(&stats);
+
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::new()),
+        overflow,
+        stats,
+        readable: Notify::new(),
+        writable: Notify::new(),
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Queues `item` without waiting, applying the overflow policy when full.
+    pub fn try_send(&self, item: T) -> Result<(), SendError> {
+        self.push(item).map_err(|(err, _)| err)
+    }
+
+    /// Queues `item`, waiting for room first if the policy is `Block`.
+    pub async fn send(&self, mut item: T) -> Result<(), SendError> {
+        loop {
+            // Registered before trying so a wakeup in between is not lost.
+            let writable = self.shared.writable.notified();
+            match self.push(item) {
+                Err((SendError::Full, rejected)) if self.shared.overflow == Overflow::Block => {
+                    item = rejected;
+                    writable.await;
+                }
+                result => return result.map_err(|(err, _)| err),
+            }
+        }
+    }
+
+    /// Queues `item`, handing it back if it was rejected.
+    fn push(&self, item: T) -> Result<(), (SendError, T)> {
+        let shared = &self.shared;
+        if shared.closed.load(Ordering::Acquire) {
+            return Err((SendError::Closed, item));
+        }
+
+        let mut queue = shared.queue.lock().expect("channel poisoned");
+        if queue.len() >= shared.stats.capacity {
+            match shared.overflow {
+                Overflow::DropOldest => {
+                    queue.pop_front();
+                    shared.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Overflow::Block | Overflow::Error => return Err((SendError::Full, item)),
+            }
+        }
+        queue.push_back(item);
+        shared.stats.depth.store(queue.len(), Ordering::Relaxed);
+        shared
+            .stats
+            .high_water
+            .fetch_max(queue.len(), Ordering::Relaxed);
+        drop(queue);
+
+        shared.readable.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.readable.notify_one();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("queue", &self.shared.stats)
+            .finish()
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next item; `None` once every sender is gone and the
+    /// queue is drained.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            let readable = self.shared.readable.notified();
+            if let Some(item) = self.pop() {
+                return Some(item);
+            }
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                return None;
+            }
+            readable.await;
+        }
+    }
+
+    fn pop(&self) -> Option<T> {
+        let mut queue = self.shared.queue.lock().expect("channel poisoned");
+        let item = queue.pop_front()?;
+        self.shared
+            .stats
+            .depth
+            .store(queue.len(), Ordering::Relaxed);
+        drop(queue);
+        self.shared.writable.notify_one();
+        Some(item)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.writable.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    async fn drain<T>(mut rx: Receiver<T>) -> Vec<T> {
+        let mut received = Vec::new();
+        while let Some(item) = rx.recv().await {
+            received.push(item);
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn error_policy_rejects_new_items_when_full() {
+        let (tx, rx) = channel("test_error", 2, Overflow::Error);
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert_eq!(tx.try_send(3), Err(SendError::Full));
+        assert_eq!(tx.send(3).await, Err(SendError::Full));
+        assert_eq!(tx.shared.stats.depth(), 2);
+        assert_eq!(tx.shared.stats.dropped(), 0);
+
+        drop(tx);
+        assert_eq!(drain(rx).await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_policy_makes_room_for_new_items() {
+        let (tx, rx) = channel("test_drop_oldest", 2, Overflow::DropOldest);
+        for item in 1..=5 {
+            tx.try_send(item).unwrap();
+        }
+        assert_eq!(tx.shared.stats.dropped(), 3);
+        assert_eq!(tx.shared.stats.high_water(), 2);
+
+        drop(tx);
+        assert_eq!(drain(rx).await, vec![4, 5]);
+    }
+
+    #[tokio::test]
+    async fn block_policy_waits_for_room() {
+        let (tx, mut rx) = channel("test_block", 1, Overflow::Block);
+        tx.send(1).await.unwrap();
+        // Without waiting, a full queue is reported rather than waited on.
+        assert_eq!(tx.try_send(2), Err(SendError::Full));
+
+        let sender = tokio::spawn(async move { tx.send(2).await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!sender.is_finished());
+
+        assert_eq!(rx.recv().await, Some(1));
+        sender.await.unwrap().unwrap();
+        assert_eq!(drain(rx).await, vec![2]);
+    }
+
+    #[test]
+    fn send_fails_once_the_receiver_is_gone() {
+        let (tx, rx) = channel::<u32>("test_closed", 1, Overflow::Block);
+        drop(rx);
+        assert_eq!(tx.try_send(1), Err(SendError::Closed));
+    }
+
+    #[test]
+    fn dropped_channels_leave_the_registry() {
+        let (tx, rx) = channel::<u32>("test_registry", 1, Overflow::Error);
+        let listed = || {
+            all_stats()
+                .iter()
+                .any(|queue| queue.name == "test_registry")
+        };
+        assert!(listed());
+        drop((tx, rx));
+        assert!(!listed());
+    }
+
+    #[test]
+    fn the_registry_is_pruned_without_being_read() {
+        for _ in 0..500 {
+            drop(channel::<u64>("test_short_lived", 1, Overflow::Error));
+        }
+        let _kept = channel::<u64>("test_short_lived", 1, Overflow::Error);
+        // Other tests' channels may be registered too, but not 500 dead ones.
+        assert!(queues().lock().unwrap().len() < 100);
+    }
+}
@@ -0,0 +1,331 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::challenges::broadcast::gossip::{DEFAULT_GOSSIP_RETRY_MAX_MS, DEFAULT_GOSSIP_RETRY_MS};
+use crate::challenges::broadcast::targets::{DEFAULT_GOSSIP_FANOUT, TargetStrategy};
+use crate::challenges::cluster::DEFAULT_GOSSIP_TICK_MS;
+use crate::challenges::generate::snowflake::IdStrategy;
+use crate::logging::Level;
+use crate::output::DEFAULT_FLUSH_IDLE_MS;
+
+// ============================================================================
+// Command Line
+// ============================================================================
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Maelstrom node for the fly.io distributed systems challenges"
+)]
+pub struct Cli {
+    /// Abort with a diagnostic when a protocol invariant is violated.
+    #[arg(long, global = true, env = "VORTEX_STRICT")]
+    pub strict: bool,
+
+    /// Keep the peer graph from the first topology message and ignore later ones.
+    #[arg(long, global = true, env = "VORTEX_FREEZE_TOPOLOGY")]
+    pub freeze_topology: bool,
+
+    /// Interval at which accumulated broadcast values are gossiped to peers.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_GOSSIP_TICK_MS",
+        value_name = "MS",
+        default_value_t = DEFAULT_GOSSIP_TICK_MS,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub gossip_tick_ms: u64,
+
+    /// Gossip as soon as a backlog of new values builds up, and stretch the
+    /// tick up to 8x while there is nothing to send.
+    #[arg(long, global = true, env = "VORTEX_ADAPTIVE_GOSSIP")]
+    pub adaptive_gossip: bool,
+
+    /// How each gossip round picks the nodes it sends deltas to.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_GOSSIP_TARGETS",
+        value_enum,
+        default_value_t = TargetStrategy::All
+    )]
+    pub gossip_targets: TargetStrategy,
+
+    /// Nodes gossiped to per round by every strategy except `all`.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_GOSSIP_FANOUT",
+        value_name = "K",
+        default_value_t = DEFAULT_GOSSIP_FANOUT
+    )]
+    pub gossip_fanout: usize,
+
+    /// How long to wait for a gossip_ok before resending the gossip. Doubles
+    /// with every retry.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_GOSSIP_RETRY_MS",
+        value_name = "MS",
+        default_value_t = DEFAULT_GOSSIP_RETRY_MS
+    )]
+    pub gossip_retry_ms: u64,
+
+    /// Upper bound on the gossip retry backoff.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_GOSSIP_RETRY_MAX_MS",
+        value_name = "MS",
+        default_value_t = DEFAULT_GOSSIP_RETRY_MAX_MS
+    )]
+    pub gossip_retry_max_ms: u64,
+
+    /// Gossip eagerly along a spanning tree pruned from the topology, and
+    /// only announce values to the other neighbours (Plumtree).
+    #[arg(long, global = true, env = "VORTEX_PLUMTREE")]
+    pub plumtree: bool,
+
+    /// Run a SWIM failure detector and hold gossip to members it declares
+    /// dead until they answer again.
+    #[arg(long, global = true, env = "VORTEX_SWIM")]
+    pub swim: bool,
+
+    /// Every N gossip rounds, compare value digests with each peer and
+    /// exchange only the values that differ. 0 disables anti-entropy.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_ANTI_ENTROPY_ROUNDS",
+        value_name = "N",
+        default_value_t = 0
+    )]
+    pub anti_entropy_rounds: u64,
+
+    /// Unacknowledged gossip messages allowed per peer; later rounds hold
+    /// their values back and send them together once one is acknowledged.
+    #[arg(long, global = true, env = "VORTEX_MAX_IN_FLIGHT", value_name = "N")]
+    pub max_in_flight: Option<usize>,
+
+    /// Flush stdout once this many reply lines are buffered.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_OUTPUT_BATCH",
+        value_name = "LINES",
+        default_value_t = 1
+    )]
+    pub output_batch: usize,
+
+    /// Flush buffered output after this long without a new line, even if the
+    /// batch isn't full.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_FLUSH_IDLE_MS",
+        value_name = "MS",
+        default_value_t = DEFAULT_FLUSH_IDLE_MS
+    )]
+    pub flush_idle_ms: u64,
+
+    /// Track broadcast values in an OR-Set and accept `retract` messages that
+    /// remove a value cluster-wide.
+    #[arg(long, global = true, env = "VORTEX_OR_SET")]
+    pub or_set: bool,
+
+    /// Deliver broadcast values to reads in causal order, holding back any
+    /// value whose causal predecessors haven't arrived yet.
+    #[arg(long, global = true, env = "VORTEX_CAUSAL")]
+    pub causal: bool,
+
+    /// Number each broadcast value a node accepts, return the number in
+    /// `broadcast_ok` and list every value's number in `read_ok`.
+    #[arg(long, global = true, env = "VORTEX_SEQUENCE")]
+    pub sequence: bool,
+
+    /// Remember the last REPLIES replies to clients and answer a request that
+    /// repeats a msg_id with its earlier reply instead of applying it again.
+    #[arg(long, global = true, env = "VORTEX_DEDUP", value_name = "REPLIES")]
+    pub dedup: Option<usize>,
+
+    /// How `generate` makes unique ids.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_ID_STRATEGY",
+        value_enum,
+        default_value_t = IdStrategy::Uuid
+    )]
+    pub id_strategy: IdStrategy,
+
+    /// Persist each node's snowflake progress under this directory so ids
+    /// stay unique across restarts.
+    #[arg(long, global = true, env = "VORTEX_ID_STATE", value_name = "DIR")]
+    pub id_state: Option<PathBuf>,
+
+    /// Log broadcast values to a write-ahead log in this directory before
+    /// acknowledging them, and reload them when a node restarts.
+    #[arg(long, global = true, env = "VORTEX_WAL", value_name = "DIR")]
+    pub wal: Option<PathBuf>,
+
+    /// Write JSON log lines at this level and above to stderr; with `debug`,
+    /// one per handled message with its node, type, msg_id and latency.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_LOG_LEVEL",
+        value_enum,
+        value_name = "LEVEL",
+        default_value_t = Level::Warn
+    )]
+    pub log_level: Level,
+
+    /// Report to stderr when one message has been in dispatch this many seconds.
+    #[arg(long, global = true, env = "VORTEX_WATCHDOG", value_name = "SECS")]
+    pub watchdog: Option<u64>,
+
+    /// Log messages-per-op and gossip fanout to stderr every this many seconds.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_METRICS",
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub metrics: Option<u64>,
+
+    /// Write per-message handler spans and gossip ticks to this file in Chrome
+    /// trace format (load it in chrome://tracing or ui.perfetto.dev).
+    #[arg(long, global = true, env = "VORTEX_TRACE", value_name = "PATH")]
+    pub trace: Option<PathBuf>,
+
+    /// Record every stdin and stdout line to a per-process file in this
+    /// directory, for replaying a real Maelstrom run.
+    #[arg(long, global = true, env = "VORTEX_CAPTURE", value_name = "DIR")]
+    pub capture: Option<PathBuf>,
+
+    /// Once no client message has arrived for this long and every queue has
+    /// drained, log a final metrics snapshot.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_QUIESCE_AFTER_MS",
+        value_name = "MS"
+    )]
+    pub quiesce_after_ms: Option<u64>,
+
+    /// Also write each node's broadcast values to this file on quiescence.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_CHECKPOINT",
+        value_name = "PATH",
+        requires = "quiesce_after_ms"
+    )]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Compact in-memory state whenever its estimated size exceeds this many entries.
+    #[arg(
+        long,
+        global = true,
+        env = "VORTEX_COMPACT_THRESHOLD",
+        value_name = "ENTRIES"
+    )]
+    pub compact_threshold: Option<usize>,
+
+    /// Workload to serve, or a capture to replay. Without one, every
+    /// workload's handlers are active.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    /// The workload being served or replayed, if one was chosen.
+    pub fn workload(&self) -> Option<Workload> {
+        match &self.command {
+            Some(Command::Workload(workload)) => Some(*workload),
+            Some(Command::Replay(replay)) => replay.workload,
+            Some(Command::Fixture(fixture)) => fixture.workload,
+            None => None,
+        }
+    }
+
+    /// The capture to read instead of stdin, when replaying.
+    pub fn replay(&self) -> Option<&Replay> {
+        match &self.command {
+            Some(Command::Replay(replay)) => Some(replay),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    #[command(flatten)]
+    Workload(Workload),
+    /// Read input from a `--capture` file instead of stdin, replaying its
+    /// inbound messages at their recorded times.
+    Replay(Replay),
+    /// Turn a `--capture` file into a regression fixture for
+    /// `tests/replay_fixtures.rs`, then exit.
+    Fixture(Fixture),
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct Replay {
+    /// File written by `--capture`.
+    #[arg(value_name = "CAPTURE")]
+    pub path: PathBuf,
+
+    /// Run on a simulated clock that jumps straight to each message's
+    /// recorded offset instead of waiting for it in real time.
+    #[arg(long)]
+    pub virtual_time: bool,
+
+    /// Workload to serve. Without one, every workload's handlers are active.
+    #[command(subcommand)]
+    pub workload: Option<Workload>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Subcommand)]
+pub enum Workload {
+    /// Challenge 1: echo.
+    Echo,
+    /// Challenge 2: unique-ids.
+    Generate,
+    /// Challenge 3: broadcast (read, topology and inter-node gossip).
+    Broadcast,
+    /// Observed-remove set CRDT (add, remove and read).
+    OrSet,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct Fixture {
+    /// File written by `--capture`.
+    #[arg(value_name = "CAPTURE")]
+    pub path: PathBuf,
+
+    /// Where to write the fixture, usually `tests/fixtures/<name>.jsonl`.
+    #[arg(value_name = "OUT")]
+    pub out: PathBuf,
+
+    /// Workload the capture was recorded with. Without one, the fixture is
+    /// replayed with every workload's handlers active.
+    #[command(subcommand)]
+    pub workload: Option<Workload>,
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn command_is_well_formed() {
+        Cli::command().debug_assert();
+    }
+}
@@ -0,0 +1,441 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+// ============================================================================
+// Vector Clocks
+// ============================================================================
+
+/// A counter per node. Missing entries count as zero.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VectorClock(HashMap<String, u64>);
+
+impl VectorClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, node: &str) -> u64 {
+        self.0.get(node).copied().unwrap_or(0)
+    }
+
+    /// Advances `node`'s entry by one and returns the new value.
+    pub fn increment(&mut self, node: &str) -> u64 {
+        let counter = self.0.entry(node.to_string()).or_default();
+        *counter += 1;
+        *counter
+    }
+
+    /// Raises `node`'s entry to at least `counter`. Returns whether it moved.
+    pub fn observe(&mut self, node: &str, counter: u64) -> bool {
+        if counter <= self.get(node) {
+            return false;
+        }
+        self.0.insert(node.to_string(), counter);
+        true
+    }
+
+    /// Pointwise maximum with `other`. Returns whether any entry moved.
+    pub fn merge(&mut self, other: &VectorClock) -> bool {
+        let mut changed = false;
+        for (node, counter) in &other.0 {
+            changed |= self.observe(node, *counter);
+        }
+        changed
+    }
+
+    /// Pointwise minimum with `other`.
+    pub fn meet(&self, other: &VectorClock) -> VectorClock {
+        VectorClock(
+            self.0
+                .iter()
+                .map(|(node, counter)| (node.clone(), (*counter).min(other.get(node))))
+                .filter(|(_, counter)| *counter > 0)
+                .collect(),
+        )
+    }
+
+    /// Causal order: `Less` if `self` happened before `other`, `None` if the
+    /// two are concurrent.
+    pub fn compare(&self, other: &VectorClock) -> Option<Ordering> {
+        let mut ordering = Ordering::Equal;
+        for node in self.0.keys().chain(other.0.keys()) {
+            let step = self.get(node).cmp(&other.get(node));
+            ordering = match (ordering, step) {
+                (ordering, Ordering::Equal) => ordering,
+                (Ordering::Equal, step) => step,
+                (ordering, step) if ordering == step => ordering,
+                _ => return None,
+            };
+        }
+        Some(ordering)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &u64)> {
+        self.0.iter()
+    }
+}
+
+impl PartialOrd for VectorClock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.compare(other)
+    }
+}
+
+// ============================================================================
+// Hybrid Logical Clock
+// ============================================================================
+
+/// A hybrid logical clock reading: wall-clock milliseconds plus a counter
+/// that orders events within the same millisecond. Ordered by `wall`, then
+/// `logical`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct HlcTimestamp {
+    pub wall: u64,
+    pub logical: u32,
+}
+
+/// Clock whose readings never go backwards, stay close to wall-clock time,
+/// and always exceed every timestamp received from another node.
+#[derive(Debug, Default)]
+pub struct Hlc {
+    last: Mutex<HlcTimestamp>,
+}
+
+impl Hlc {
+    /// Reading for a local event or an outgoing message.
+    pub fn now(&self) -> HlcTimestamp {
+        let mut last = self.last.lock().expect("hlc poisoned");
+        *last = advance(*last, None);
+        *last
+    }
+
+    /// Folds in a timestamp received from another node.
+    pub fn update(&self, remote: HlcTimestamp) -> HlcTimestamp {
+        let mut last = self.last.lock().expect("hlc poisoned");
+        *last = advance(*last, Some(remote));
+        *last
+    }
+}
+
+fn advance(last: HlcTimestamp, remote: Option<HlcTimestamp>) -> HlcTimestamp {
+    let physical = wall_clock_ms();
+    let remote = remote.unwrap_or_default();
+    let wall = physical.max(last.wall).max(remote.wall);
+    let logical = match (wall == last.wall, wall == remote.wall) {
+        (true, true) => last.logical.max(remote.logical) + 1,
+        (true, false) => last.logical + 1,
+        (false, true) => remote.logical + 1,
+        (false, false) => 0,
+    };
+    HlcTimestamp { wall, logical }
+}
+
+static HLC: OnceLock<Hlc> = OnceLock::new();
+
+/// The process-wide hybrid logical clock.
+pub fn hlc() -> &'static Hlc {
+    HLC.get_or_init(Hlc::default)
+}
+
+// ============================================================================
+// Time Source
+// ============================================================================
+
+/// A wait on a [`TimeSource`].
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Where every subsystem reads the time from and waits on it: gossip acks and
+/// retries, RPC round trips and timeouts, the HLC, id generation, scheduled
+/// tasks, output flushing, capture replay, metrics, logging and the health
+/// checks.
+pub trait TimeSource: Send + Sync {
+    /// Milliseconds since the Unix epoch.
+    fn wall_clock_ms(&self) -> u64;
+
+    /// Monotonic time, for measuring intervals.
+    fn now(&self) -> Instant;
+
+    /// Completes once `now()` has reached `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> Sleep;
+}
+
+/// The operating system's clocks, with waits on tokio's timer.
+pub struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn wall_clock_ms(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0)
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+/// A clock that only moves when told to, so runs under virtual time see
+/// every timer fire at a chosen point.
+pub struct SimulatedTimeSource {
+    start: Instant,
+    start_wall_ms: u64,
+    state: Mutex<Simulated>,
+}
+
+/// How far a [`SimulatedTimeSource`] has moved, and the waits on it that
+/// haven't come due yet.
+#[derive(Debug, Default)]
+struct Simulated {
+    offset: Duration,
+    timers: Vec<(Instant, oneshot::Sender<()>)>,
+}
+
+impl SimulatedTimeSource {
+    pub fn new(start_wall_ms: u64) -> Self {
+        SimulatedTimeSource {
+            start: Instant::now(),
+            start_wall_ms,
+            state: Mutex::new(Simulated::default()),
+        }
+    }
+
+    /// Moves the clock forward by `by`, completing every wait that comes due.
+    pub fn advance(&self, by: Duration) {
+        let due = {
+            let mut state = self.state.lock().expect("simulated clock poisoned");
+            state.offset += by;
+            let now = self.start + state.offset;
+            let (due, pending) = std::mem::take(&mut state.timers)
+                .into_iter()
+                .partition(|(deadline, _)| *deadline <= now);
+            state.timers = pending;
+            due
+        };
+        for (_, timer) in due {
+            let _ = timer.send(());
+        }
+    }
+
+    /// Moves the clock forward to `instant`, if it isn't there yet.
+    pub fn advance_to(&self, instant: Instant) {
+        self.advance(instant.saturating_duration_since(self.now()));
+    }
+
+    fn offset(&self) -> Duration {
+        self.state.lock().expect("simulated clock poisoned").offset
+    }
+}
+
+impl TimeSource for SimulatedTimeSource {
+    fn wall_clock_ms(&self) -> u64 {
+        self.start_wall_ms + self.offset().as_millis() as u64
+    }
+
+    fn now(&self) -> Instant {
+        self.start + self.offset()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        let mut state = self.state.lock().expect("simulated clock poisoned");
+        if deadline <= self.start + state.offset {
+            return Box::pin(std::future::ready(()));
+        }
+        let (timer, due) = oneshot::channel();
+        state.timers.push((deadline, timer));
+        Box::pin(async move {
+            let _ = due.await;
+        })
+    }
+}
+
+static TIME_SOURCE: OnceLock<Arc<dyn TimeSource>> = OnceLock::new();
+
+/// Replaces the system clocks for the rest of the process. Has no effect once
+/// anything has read the time.
+pub fn set_time_source(source: Arc<dyn TimeSource>) {
+    let _ = TIME_SOURCE.set(source);
+}
+
+#[cfg(test)]
+thread_local! {
+    static THREAD_SOURCE: std::cell::RefCell<Option<Arc<dyn TimeSource>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// Reads the time from `source` on the calling thread until the returned
+/// guard is dropped. A current-thread runtime runs every task on the thread
+/// driving it, so a test can put a whole node on virtual time without
+/// affecting tests on other threads.
+#[cfg(test)]
+pub fn use_on_this_thread(source: Arc<dyn TimeSource>) -> impl Drop {
+    struct Restore;
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            THREAD_SOURCE.with(|current| current.borrow_mut().take());
+        }
+    }
+    THREAD_SOURCE.with(|current| *current.borrow_mut() = Some(source));
+    Restore
+}
+
+fn time_source() -> Arc<dyn TimeSource> {
+    #[cfg(test)]
+    if let Some(source) = THREAD_SOURCE.with(|current| current.borrow().clone()) {
+        return source;
+    }
+    TIME_SOURCE
+        .get_or_init(|| Arc::new(SystemTimeSource))
+        .clone()
+}
+
+/// Milliseconds since the Unix epoch, from the process time source.
+pub fn wall_clock_ms() -> u64 {
+    time_source().wall_clock_ms()
+}
+
+/// Monotonic time from the process time source.
+pub fn now() -> Instant {
+    time_source().now()
+}
+
+/// Time passed since `earlier`, by the process time source.
+pub fn elapsed(earlier: Instant) -> Duration {
+    now().saturating_duration_since(earlier)
+}
+
+/// Waits until the process time source reaches `deadline`.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    time_source().sleep_until(deadline)
+}
+
+/// Waits for `duration` by the process time source.
+pub fn sleep(duration: Duration) -> Sleep {
+    let source = time_source();
+    source.sleep_until(source.now() + duration)
+}
+
+/// Runs `future` for at most `duration` by the process time source, giving
+/// `None` if it didn't finish in time.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    tokio::select! {
+        output = future => Some(output),
+        _ = sleep(duration) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(entries: &[(&str, u64)]) -> VectorClock {
+        let mut clock = VectorClock::new();
+        for (node, counter) in entries {
+            clock.observe(node, *counter);
+        }
+        clock
+    }
+
+    #[test]
+    fn vector_clocks_order_causally() {
+        let before = vector(&[("n1", 1), ("n2", 2)]);
+        let after = vector(&[("n1", 2), ("n2", 2)]);
+        let concurrent = vector(&[("n1", 3), ("n2", 1)]);
+        assert_eq!(before.compare(&after), Some(Ordering::Less));
+        assert_eq!(after.compare(&before), Some(Ordering::Greater));
+        assert_eq!(before.compare(&before.clone()), Some(Ordering::Equal));
+        assert_eq!(after.compare(&concurrent), None);
+        // Missing entries count as zero.
+        assert_eq!(VectorClock::new().compare(&before), Some(Ordering::Less));
+        assert_eq!(vector(&[("n1", 0)]), VectorClock::new());
+    }
+
+    #[test]
+    fn meet_is_the_pointwise_minimum() {
+        let a = vector(&[("n1", 3), ("n2", 1), ("n3", 4)]);
+        let b = vector(&[("n1", 2), ("n2", 5)]);
+        let meet = a.meet(&b);
+        assert_eq!(meet, vector(&[("n1", 2), ("n2", 1)]));
+        assert_eq!(meet, b.meet(&a));
+        assert!(meet <= a && meet <= b);
+    }
+
+    #[test]
+    fn hlc_readings_only_increase() {
+        let hlc = Hlc::default();
+        let mut last = hlc.now();
+        for _ in 0..1000 {
+            let next = hlc.now();
+            assert!(next > last);
+            last = next;
+        }
+    }
+
+    #[test]
+    fn hlc_stays_ahead_of_remote_timestamps() {
+        let hlc = Hlc::default();
+        let remote = HlcTimestamp {
+            wall: wall_clock_ms() + 60_000,
+            logical: 7,
+        };
+        let merged = hlc.update(remote);
+        assert_eq!((merged.wall, merged.logical), (remote.wall, 8));
+        assert!(hlc.now() > merged);
+        // An older remote timestamp doesn't pull the clock back.
+        assert!(hlc.update(HlcTimestamp::default()) > merged);
+    }
+
+    fn simulated() -> (Arc<SimulatedTimeSource>, impl Drop) {
+        let source = Arc::new(SimulatedTimeSource::new(1_000));
+        let guard = use_on_this_thread(source.clone());
+        (source, guard)
+    }
+
+    #[tokio::test]
+    async fn simulated_sleep_completes_when_advanced_past_its_deadline() {
+        let (source, _guard) = simulated();
+        let sleeper = tokio::spawn(sleep(Duration::from_secs(5)));
+        tokio::task::yield_now().await;
+
+        source.advance(Duration::from_secs(4));
+        tokio::task::yield_now().await;
+        assert!(!sleeper.is_finished());
+
+        source.advance(Duration::from_secs(1));
+        sleeper.await.unwrap();
+        assert_eq!(wall_clock_ms(), 6_000);
+    }
+
+    #[tokio::test]
+    async fn simulated_timeout_only_fires_in_virtual_time() {
+        let (source, _guard) = simulated();
+        let pending = tokio::spawn(timeout(
+            Duration::from_millis(100),
+            std::future::pending::<()>(),
+        ));
+        tokio::task::yield_now().await;
+        // Real time passing doesn't move the simulated clock.
+        std::thread::sleep(Duration::from_millis(150));
+        tokio::task::yield_now().await;
+        assert!(!pending.is_finished());
+
+        source.advance(Duration::from_millis(100));
+        assert_eq!(pending.await.unwrap(), None);
+        assert_eq!(
+            timeout(Duration::from_millis(100), async { 7 }).await,
+            Some(7)
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use crate::challenges::{broadcast::BroadcastData, cluster::global_cluster};
+use crate::{logging, scheduler};
+
+// ============================================================================
+// State Compaction
+// ============================================================================
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Starts a task that estimates the size of the node's state once a second
+/// and compacts it whenever the estimate exceeds `threshold` entries. When a
+/// pass leaves the state above the threshold, the next one waits until the
+/// state has grown by half again, so state that can't shrink isn't compacted
+/// every second.
+pub fn spawn(threshold: usize) {
+    let mut trigger = threshold;
+    scheduler::every("compaction_timer", CHECK_INTERVAL, move |_| {
+        let before = estimate();
+        if before > trigger {
+            let freed = compact();
+            let after = estimate();
+            trigger = if after > threshold {
+                after + after / 2
+            } else {
+                threshold
+            };
+            logging::event(
+                logging::Level::Info,
+                "compaction: state compacted",
+                serde_json::json!({
+                    "before": before,
+                    "after": after,
+                    "freed": freed,
+                    "threshold": threshold,
+                    "next_at": trigger,
+                }),
+            );
+        }
+        async {}
+    });
+}
+
+/// Approximate state size in entries: broadcast values, per-peer tracking
+/// and OR-Set tombstones.
+fn estimate() -> usize {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    cluster
+        .nodes
+        .values()
+        .filter_map(|node| {
+            let broadcast_data = node.broadcast_data.lock();
+            broadcast_data.as_ref().map(BroadcastData::size_estimate)
+        })
+        .sum()
+}
+
+/// Drops delta state of peers the node no longer gossips to, folds runs of
+/// consecutive broadcast values into ranges and trims the insertion log.
+/// Returns how many entries were freed.
+fn compact() -> usize {
+    let cluster = global_cluster().read().expect("cluster lock poisoned");
+    let mut freed = 0;
+    for node in cluster.nodes.values() {
+        if let Some(broadcast_data) = node.broadcast_data.lock().as_mut() {
+            // Farthest-first gossip also targets non-neighbours.
+            let mut targets = node.peers.clone();
+            targets.extend(node.distances.keys().cloned());
+            let before = broadcast_data.size_estimate();
+            broadcast_data.retain_peers(&targets);
+            freed += before - broadcast_data.size_estimate();
+            freed += broadcast_data.compact();
+        }
+    }
+    freed
+}
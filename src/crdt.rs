@@ -0,0 +1,794 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::VectorClock;
+use crate::ranges::RangeSet;
+use crate::{Message, Payload};
+
+// ============================================================================
+// CRDT Interface
+// ============================================================================
+
+/// A state-based replicated data type whose replicas converge by exchanging
+/// deltas and merging them in any order.
+pub trait Crdt {
+    /// What a read of the replica returns.
+    type Value;
+    /// What replicas exchange; at worst the full state.
+    type Delta: Clone;
+
+    /// Folds a delta sent by `from` into this replica, held by `node_id`.
+    /// Returns whether anything changed.
+    fn merge(&mut self, delta: &Self::Delta, node_id: &str, from: &str) -> bool;
+
+    /// Everything a replica that has merged up to `version` is missing.
+    fn delta_since(&self, version: u64) -> Self::Delta;
+
+    fn value(&self) -> Self::Value;
+
+    /// Increases on every change to the replica.
+    fn version(&self) -> u64;
+}
+
+/// Decides which peers are sent a CRDT's delta each gossip round. Deltas are
+/// not acknowledged, so every `resync_rounds` rounds each peer is sent the
+/// full state again. How small a delta is depends on the CRDT's
+/// `delta_since`; for [`ORSet`] it is always the full state.
+#[derive(Debug)]
+pub struct Replicator {
+    /// Version of the CRDT last sent to each peer.
+    sent: Mutex<HashMap<String, u64>>,
+    resync_rounds: u64,
+}
+
+impl Replicator {
+    pub fn new(resync_rounds: u64) -> Self {
+        Self {
+            sent: Mutex::new(HashMap::new()),
+            resync_rounds,
+        }
+    }
+
+    /// The delta for each of `peers` that hasn't been sent the current
+    /// version, or for all of them on a resync round.
+    pub fn deltas<C: Crdt>(
+        &self,
+        crdt: &C,
+        peers: &[String],
+        round: u64,
+    ) -> Vec<(String, C::Delta)> {
+        let version = crdt.version();
+        let resync = round.is_multiple_of(self.resync_rounds);
+        let mut sent = self.sent.lock().expect("replicator state poisoned");
+        peers
+            .iter()
+            .filter_map(|peer| {
+                let previous = sent.insert(peer.clone(), version);
+                let since = match previous {
+                    Some(previous) if !resync => previous,
+                    _ => 0,
+                };
+                if !resync && previous == Some(version) {
+                    return None;
+                }
+                Some((peer.clone(), crdt.delta_since(since)))
+            })
+            .collect()
+    }
+
+    /// [`Replicator::deltas`] from `node_id` to each of its `peers`, every
+    /// delta wrapped by `body` in a one-way message without a msg_id.
+    pub fn messages<C: Crdt>(
+        &self,
+        crdt: &C,
+        node_id: &str,
+        peers: &[String],
+        round: u64,
+        body: impl Fn(C::Delta) -> Payload,
+    ) -> Vec<Message<Payload>> {
+        let peers: Vec<String> = peers
+            .iter()
+            .filter(|peer| *peer != node_id)
+            .cloned()
+            .collect();
+        self.deltas(crdt, &peers, round)
+            .into_iter()
+            .map(|(peer, delta)| Message {
+                src: node_id.to_string(),
+                dest: peer,
+                body: body(delta),
+            })
+            .collect()
+    }
+}
+
+// ============================================================================
+// Grow-Only Set
+// ============================================================================
+
+/// A set that only grows, keeping values in insertion order so a delta only
+/// has to look at the values added since the version it starts from.
+/// [`GSet::compact`] folds consecutive values into runs and drops the part
+/// of the insertion log no delta will start from again.
+#[derive(Debug, Clone, Default)]
+pub struct GSet {
+    values: RangeSet,
+    log: Vec<u64>,
+    /// Version of the first value left in `log`.
+    log_start: u64,
+}
+
+impl GSet {
+    pub fn insert(&mut self, value: u64) -> bool {
+        let inserted = self.values.insert(value);
+        if inserted {
+            self.log.push(value);
+        }
+        inserted
+    }
+
+    /// Values added since the set held `version` of them, oldest first, or
+    /// `None` once that part of the log has been compacted away.
+    pub fn added_since(&self, version: u64) -> Option<&[u64]> {
+        let skip = version.checked_sub(self.log_start)? as usize;
+        Some(&self.log[skip.min(self.log.len())..])
+    }
+
+    pub fn values(&self) -> &RangeSet {
+        &self.values
+    }
+
+    /// Values and log positions held: the set's size in memory.
+    pub fn entries(&self) -> usize {
+        self.values.entries() + self.log.len()
+    }
+
+    /// Folds consecutive values into runs and drops the log before
+    /// `version`, the oldest a delta still has to start from. Returns how
+    /// many entries were freed.
+    pub fn compact(&mut self, version: u64) -> usize {
+        let version = version.clamp(self.log_start, self.version());
+        let dropped = (version - self.log_start) as usize;
+        self.log.drain(..dropped);
+        self.log.shrink_to_fit();
+        self.log_start = version;
+        dropped + self.values.compact()
+    }
+}
+
+impl Crdt for GSet {
+    type Value = HashSet<u64>;
+    type Delta = HashSet<u64>;
+
+    fn merge(&mut self, delta: &HashSet<u64>, _node_id: &str, _from: &str) -> bool {
+        let before = self.version();
+        for value in delta {
+            self.insert(*value);
+        }
+        self.version() != before
+    }
+
+    /// The whole set when the log no longer reaches back to `version`.
+    fn delta_since(&self, version: u64) -> HashSet<u64> {
+        match self.added_since(version) {
+            Some(added) => added.iter().copied().collect(),
+            None => self.value(),
+        }
+    }
+
+    fn value(&self) -> HashSet<u64> {
+        self.values.iter().collect()
+    }
+
+    fn version(&self) -> u64 {
+        self.log_start + self.log.len() as u64
+    }
+}
+
+// ============================================================================
+// Observed-Remove Set
+// ============================================================================
+
+/// Unique identity of one add: the node that performed it and a sequence
+/// number that node never reuses.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Tag {
+    pub node: String,
+    pub seq: u64,
+}
+
+/// When a tombstone was created: the removing node and that node's running
+/// count of removes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    pub node: String,
+    pub counter: u64,
+}
+
+/// A set where a remove only cancels the adds it has observed, so an add
+/// concurrent with a remove survives the merge.
+///
+/// Tombstones are garbage-collected once they are stable: every member has
+/// reported a vector clock covering them, so no member can still hold (and
+/// re-send) the adds they cancel.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ORSet {
+    /// Live add-tags of every present value. Maps are sent as lists of pairs,
+    /// since non-string keys don't survive the internally tagged `Payload`.
+    #[serde(with = "pairs")]
+    adds: HashMap<u64, HashSet<Tag>>,
+    /// Tags cancelled by a remove; kept until stable so a late merge can't
+    /// revive them.
+    #[serde(with = "pairs")]
+    tombstones: HashMap<Tag, Stamp>,
+    /// The latest clock of removes seen that each member has reported, this
+    /// node's included.
+    clocks: HashMap<String, VectorClock>,
+    /// Bumped on every local change or merge that changes the set.
+    #[serde(skip)]
+    version: u64,
+}
+
+impl ORSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, value: u64, tag: Tag) {
+        if !self.tombstones.contains_key(&tag) && self.adds.entry(value).or_default().insert(tag) {
+            self.version += 1;
+        }
+    }
+
+    /// Tombstones every add of `value` seen so far on `node_id`. Returns
+    /// whether the value was present.
+    pub fn remove(&mut self, value: u64, node_id: &str) -> bool {
+        let Some(tags) = self.adds.remove(&value) else {
+            return false;
+        };
+        let clock = self.clocks.entry(node_id.to_string()).or_default();
+        let stamp = Stamp {
+            node: node_id.to_string(),
+            counter: clock.increment(node_id),
+        };
+        for tag in tags {
+            self.tombstones.insert(tag, stamp.clone());
+        }
+        self.version += 1;
+        true
+    }
+
+    /// Folds the state `from` sent into this set, held by `node_id`. Returns
+    /// whether anything changed. A state older than one already merged from
+    /// the same sender is ignored, since it may carry adds whose tombstones
+    /// have since been collected.
+    pub fn merge(&mut self, other: &ORSet, node_id: &str, from: &str) -> bool {
+        if let (Some(known), Some(incoming)) = (self.clocks.get(from), other.clocks.get(from))
+            && !matches!(
+                known.compare(incoming),
+                Some(Ordering::Less | Ordering::Equal)
+            )
+        {
+            return false;
+        }
+
+        let before = self.version;
+        let own = self.clocks.entry(node_id.to_string()).or_default();
+        for (tag, stamp) in &other.tombstones {
+            own.observe(&stamp.node, stamp.counter);
+            if !self.tombstones.contains_key(tag) {
+                self.tombstones.insert(tag.clone(), stamp.clone());
+                self.version += 1;
+            }
+        }
+        self.adds.retain(|_, tags| {
+            tags.retain(|tag| !other.tombstones.contains_key(tag));
+            !tags.is_empty()
+        });
+        for (value, tags) in &other.adds {
+            for tag in tags {
+                self.add(*value, tag.clone());
+            }
+        }
+
+        for (member, clock) in &other.clocks {
+            if member == node_id {
+                continue;
+            }
+            if self.clocks.entry(member.clone()).or_default().merge(clock) {
+                self.version += 1;
+            }
+        }
+        self.version != before
+    }
+
+    /// Pointwise minimum of every member's reported clock: the removes all of
+    /// them are known to have seen.
+    pub fn stability_frontier(&self, members: &[String]) -> VectorClock {
+        let mut frontier: Option<VectorClock> = None;
+        for member in members {
+            let Some(clock) = self.clocks.get(member) else {
+                return VectorClock::new();
+            };
+            frontier = Some(match frontier {
+                None => clock.clone(),
+                Some(frontier) => frontier.meet(clock),
+            });
+        }
+        frontier.unwrap_or_default()
+    }
+
+    /// Drops tombstones every member has seen. Returns how many were dropped.
+    pub fn collect_garbage(&mut self, members: &[String]) -> usize {
+        let frontier = self.stability_frontier(members);
+        let before = self.tombstones.len();
+        self.tombstones
+            .retain(|_, stamp| stamp.counter > frontier.get(&stamp.node));
+        before - self.tombstones.len()
+    }
+
+    pub fn elements(&self) -> HashSet<u64> {
+        self.adds.keys().copied().collect()
+    }
+
+    /// Whether the set holds no adds and no tombstones.
+    pub fn is_empty(&self) -> bool {
+        self.adds.is_empty() && self.tombstones.is_empty()
+    }
+
+    /// The highest add-tag sequence number `node` has used that this set
+    /// has seen, live or tombstoned.
+    pub fn max_seq(&self, node: &str) -> Option<u64> {
+        let live = self.adds.values().flatten();
+        live.chain(self.tombstones.keys())
+            .filter(|tag| tag.node == node)
+            .map(|tag| tag.seq)
+            .max()
+    }
+
+    pub fn tombstone_count(&self) -> usize {
+        self.tombstones.len()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+/// Full-state gossip, not delta gossip: adds and tombstones aren't tracked
+/// per version, so `delta_since` ignores the version and returns the whole
+/// set, and `Replicator` sends every peer that is behind the entire state.
+impl Crdt for ORSet {
+    type Value = HashSet<u64>;
+    type Delta = ORSet;
+
+    fn merge(&mut self, delta: &ORSet, node_id: &str, from: &str) -> bool {
+        ORSet::merge(self, delta, node_id, from)
+    }
+
+    fn delta_since(&self, _version: u64) -> ORSet {
+        self.clone()
+    }
+
+    fn value(&self) -> HashSet<u64> {
+        self.elements()
+    }
+
+    fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+// ============================================================================
+// Observed-Remove Set Without Tombstones
+// ============================================================================
+
+/// An observed-remove set that keeps no tombstones. Each add carries a
+/// unique tag, and `context` records, per node, the highest tag sequence
+/// number this replica has seen, present or since removed. A remove just
+/// drops the value's tags; a merge then drops any tag the other side has
+/// seen (it is covered by the other side's context) but no longer holds.
+///
+/// A node's tags must start at 1 and only ever grow, and replicas must
+/// exchange their full state, so that a context entry of `n` means every tag
+/// of that node up to `n` has been seen.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ORSwot {
+    /// Tags of the adds present for each value.
+    #[serde(with = "pairs")]
+    entries: HashMap<u64, HashSet<Tag>>,
+    context: VectorClock,
+    /// Bumped on every local change or merge that changes the set.
+    #[serde(skip)]
+    version: u64,
+}
+
+impl ORSwot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, value: u64, tag: Tag) {
+        if tag.seq <= self.context.get(&tag.node) {
+            return;
+        }
+        self.context.observe(&tag.node, tag.seq);
+        self.entries.entry(value).or_default().insert(tag);
+        self.version += 1;
+    }
+
+    /// Drops every add of `value` seen so far. Returns whether the value was
+    /// present.
+    pub fn remove(&mut self, value: u64) -> bool {
+        let removed = self.entries.remove(&value).is_some();
+        if removed {
+            self.version += 1;
+        }
+        removed
+    }
+
+    /// Folds another replica's full state into this one. Returns whether
+    /// anything changed.
+    pub fn merge(&mut self, other: &ORSwot) -> bool {
+        let mut changed = false;
+        self.entries.retain(|value, tags| {
+            let theirs = other.entries.get(value);
+            tags.retain(|tag| {
+                let keep = theirs.is_some_and(|theirs| theirs.contains(tag))
+                    || tag.seq > other.context.get(&tag.node);
+                changed |= !keep;
+                keep
+            });
+            !tags.is_empty()
+        });
+        for (value, tags) in &other.entries {
+            for tag in tags {
+                if tag.seq > self.context.get(&tag.node) {
+                    self.entries.entry(*value).or_default().insert(tag.clone());
+                    changed = true;
+                }
+            }
+        }
+        changed |= self.context.merge(&other.context);
+        if changed {
+            self.version += 1;
+        }
+        changed
+    }
+
+    pub fn elements(&self) -> HashSet<u64> {
+        self.entries.keys().copied().collect()
+    }
+
+    /// The highest tag sequence number `node` has used that this set has
+    /// seen, present or removed.
+    pub fn max_seq(&self, node: &str) -> Option<u64> {
+        Some(self.context.get(node)).filter(|seq| *seq > 0)
+    }
+}
+
+/// Full-state gossip, as the context is only exact for full states.
+impl Crdt for ORSwot {
+    type Value = HashSet<u64>;
+    type Delta = ORSwot;
+
+    fn merge(&mut self, delta: &ORSwot, _node_id: &str, _from: &str) -> bool {
+        ORSwot::merge(self, delta)
+    }
+
+    fn delta_since(&self, _version: u64) -> ORSwot {
+        self.clone()
+    }
+
+    fn value(&self) -> HashSet<u64> {
+        self.elements()
+    }
+
+    fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+/// (De)serializes a map as a sequence of `[key, value]` pairs.
+mod pairs {
+    use std::collections::HashMap;
+    use std::hash::Hash;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let pairs = Vec::<(K, V)>::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn tag(node: &str, seq: u64) -> Tag {
+        Tag {
+            node: node.to_string(),
+            seq,
+        }
+    }
+
+    fn members() -> Vec<String> {
+        vec!["n1".to_string(), "n2".to_string()]
+    }
+
+    #[test]
+    fn gset_merge_is_idempotent_and_deltas_start_at_the_version() {
+        let mut a = GSet::default();
+        a.insert(1);
+        a.insert(2);
+        let version = a.version();
+        a.insert(3);
+        assert_eq!(a.delta_since(version), HashSet::from([3]));
+
+        let mut b = GSet::default();
+        assert!(Crdt::merge(&mut b, &a.delta_since(0), "n2", "n1"));
+        assert!(!Crdt::merge(&mut b, &a.delta_since(0), "n2", "n1"));
+        assert_eq!(b.value(), a.value());
+    }
+
+    #[test]
+    fn or_set_merge_is_commutative() {
+        let mut a = ORSet::new();
+        a.add(1, tag("n1", 1));
+        a.add(2, tag("n1", 2));
+        a.remove(2, "n1");
+        let mut b = ORSet::new();
+        b.add(2, tag("n2", 1));
+        b.add(3, tag("n2", 2));
+
+        let mut ab = a.clone();
+        ab.merge(&b, "n1", "n2");
+        let mut ba = b.clone();
+        ba.merge(&a, "n2", "n1");
+        assert_eq!(ab.elements(), ba.elements());
+        // The remove on n1 only cancels the add it saw, so n2's add of 2
+        // survives.
+        assert_eq!(ab.elements(), HashSet::from([1, 2, 3]));
+    }
+
+    #[test]
+    fn or_set_merge_is_idempotent() {
+        let mut a = ORSet::new();
+        a.add(1, tag("n1", 1));
+        a.add(2, tag("n1", 2));
+        a.remove(1, "n1");
+        let mut b = ORSet::new();
+
+        assert!(b.merge(&a, "n2", "n1"));
+        let (elements, version) = (b.elements(), b.version());
+        assert!(!b.merge(&a, "n2", "n1"));
+        assert_eq!((b.elements(), b.version()), (elements, version));
+    }
+
+    #[test]
+    fn collected_tombstones_do_not_let_stale_state_revive_a_value() {
+        let mut n1 = ORSet::new();
+        n1.add(7, tag("n1", 1));
+        let mut n2 = ORSet::new();
+        n2.merge(&n1, "n2", "n1");
+        let stale = n2.clone();
+
+        n2.remove(7, "n2");
+        n1.merge(&n2, "n1", "n2");
+        n2.merge(&n1, "n2", "n1");
+        assert!(n1.elements().is_empty());
+
+        // Both members have seen the remove, so its tombstone can go.
+        assert_eq!(n1.collect_garbage(&members()), 1);
+        assert_eq!(n1.tombstone_count(), 0);
+
+        // n2's state from before the remove still carries the add.
+        assert!(!n1.merge(&stale, "n1", "n2"));
+        assert!(n1.elements().is_empty());
+    }
+
+    #[test]
+    fn tombstones_stay_until_every_member_has_seen_them() {
+        let mut n1 = ORSet::new();
+        n1.add(7, tag("n1", 1));
+        n1.remove(7, "n1");
+        assert_eq!(n1.collect_garbage(&members()), 0);
+        assert_eq!(n1.tombstone_count(), 1);
+    }
+
+    /// Applies random adds, removes, merges and collections to three
+    /// replicas. After every step no replica holds a live tag it also
+    /// tombstoned and versions only grow. Once every replica has merged
+    /// every other, they all hold exactly the values with an add no remove
+    /// observed.
+    #[test]
+    fn random_operations_keep_the_or_set_invariants() {
+        let nodes = ["n1", "n2", "n3"].map(String::from);
+        for seed in 0..200 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut replicas = [ORSet::new(), ORSet::new(), ORSet::new()];
+            let mut seqs = [0; 3];
+            let mut added: HashMap<Tag, u64> = HashMap::new();
+            let mut cancelled: HashSet<Tag> = HashSet::new();
+            for step in 0..100 {
+                let i = rng.random_range(0..3);
+                let value = rng.random_range(0..8);
+                let before = replicas.each_ref().map(ORSet::version);
+                match rng.random_range(0..10) {
+                    0..4 => {
+                        seqs[i] += 1;
+                        let tag = tag(&nodes[i], seqs[i]);
+                        added.insert(tag.clone(), value);
+                        replicas[i].add(value, tag);
+                        assert!(replicas[i].elements().contains(&value));
+                    }
+                    4..6 => {
+                        let observed = replicas[i].adds.get(&value).cloned();
+                        cancelled.extend(observed.into_iter().flatten());
+                        replicas[i].remove(value, &nodes[i]);
+                        assert!(!replicas[i].elements().contains(&value));
+                    }
+                    6..9 => {
+                        let from = rng.random_range(0..3);
+                        let other = replicas[from].clone();
+                        replicas[i].merge(&other, &nodes[i], &nodes[from]);
+                    }
+                    _ => {
+                        replicas[i].collect_garbage(&nodes);
+                    }
+                }
+                for (replica, before) in replicas.iter().zip(before) {
+                    assert!(replica.version() >= before, "seed {seed} step {step}");
+                    let live = replica.adds.values().flatten();
+                    let revived = live.filter(|tag| replica.tombstones.contains_key(tag));
+                    assert_eq!(revived.count(), 0, "seed {seed} step {step}");
+                }
+            }
+
+            for _ in 0..2 {
+                for i in 0..3 {
+                    for from in 0..3 {
+                        let other = replicas[from].clone();
+                        replicas[i].merge(&other, &nodes[i], &nodes[from]);
+                    }
+                }
+            }
+            let expected: HashSet<u64> = added
+                .iter()
+                .filter(|(tag, _)| !cancelled.contains(tag))
+                .map(|(_, value)| *value)
+                .collect();
+            for replica in &replicas {
+                assert_eq!(replica.elements(), expected, "seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn orswot_remove_only_cancels_the_adds_it_saw() {
+        let mut a = ORSwot::new();
+        a.add(1, tag("n1", 1));
+        a.add(2, tag("n1", 2));
+        let mut b = a.clone();
+        a.remove(2);
+        b.add(2, tag("n2", 1));
+        b.add(3, tag("n2", 2));
+
+        let mut ab = a.clone();
+        ab.merge(&b);
+        let mut ba = b.clone();
+        ba.merge(&a);
+        assert_eq!(ab.elements(), ba.elements());
+        // n2's add of 2 is concurrent with n1's remove and survives it.
+        assert_eq!(ab.elements(), HashSet::from([1, 2, 3]));
+        assert_eq!(ab.entries[&2], HashSet::from([tag("n2", 1)]));
+    }
+
+    #[test]
+    fn orswot_stale_state_does_not_revive_a_removed_value() {
+        let mut n1 = ORSwot::new();
+        n1.add(7, tag("n1", 1));
+        let mut n2 = ORSwot::new();
+        n2.merge(&n1);
+        let stale = n2.clone();
+
+        n2.remove(7);
+        assert!(n2.entries.is_empty());
+        n1.merge(&n2);
+        assert!(n1.elements().is_empty());
+
+        // Nothing of the remove is kept, yet the context covers the add.
+        assert!(!n1.merge(&stale));
+        assert!(n1.elements().is_empty());
+        let (elements, version) = (n1.elements(), n1.version());
+        assert!(!n1.merge(&n2));
+        assert_eq!((n1.elements(), n1.version()), (elements, version));
+    }
+
+    /// Applies random adds, removes and full-state merges to three replicas.
+    /// After every step each replica's context covers every tag it holds and
+    /// versions only grow. Once every replica has merged every other, they
+    /// all hold exactly the values with an add no remove observed.
+    #[test]
+    fn random_operations_keep_the_orswot_invariants() {
+        let nodes = ["n1", "n2", "n3"].map(String::from);
+        for seed in 0..200 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut replicas = [ORSwot::new(), ORSwot::new(), ORSwot::new()];
+            let mut seqs = [0; 3];
+            let mut added: HashMap<Tag, u64> = HashMap::new();
+            let mut cancelled: HashSet<Tag> = HashSet::new();
+            for step in 0..100 {
+                let i = rng.random_range(0..3);
+                let value = rng.random_range(0..8);
+                let before = replicas.each_ref().map(ORSwot::version);
+                match rng.random_range(0..10) {
+                    0..4 => {
+                        seqs[i] += 1;
+                        let tag = tag(&nodes[i], seqs[i]);
+                        added.insert(tag.clone(), value);
+                        replicas[i].add(value, tag);
+                        assert!(replicas[i].elements().contains(&value));
+                    }
+                    4..6 => {
+                        let observed = replicas[i].entries.get(&value).cloned();
+                        cancelled.extend(observed.into_iter().flatten());
+                        replicas[i].remove(value);
+                        assert!(!replicas[i].elements().contains(&value));
+                    }
+                    _ => {
+                        let from = rng.random_range(0..3);
+                        let other = replicas[from].clone();
+                        replicas[i].merge(&other);
+                    }
+                }
+                for (replica, before) in replicas.iter().zip(before) {
+                    assert!(replica.version() >= before, "seed {seed} step {step}");
+                    let mut tags = replica.entries.values().flatten();
+                    let covered = |tag: &Tag| tag.seq <= replica.context.get(&tag.node);
+                    assert!(tags.all(covered), "seed {seed} step {step}");
+                }
+            }
+
+            for i in 0..3 {
+                for from in 0..3 {
+                    let other = replicas[from].clone();
+                    replicas[i].merge(&other);
+                }
+            }
+            for i in 0..3 {
+                let other = replicas[2].clone();
+                replicas[i].merge(&other);
+            }
+            let expected: HashSet<u64> = added
+                .iter()
+                .filter(|(tag, _)| !cancelled.contains(tag))
+                .map(|(_, value)| *value)
+                .collect();
+            for replica in &replicas {
+                assert_eq!(replica.elements(), expected, "seed {seed}");
+            }
+        }
+    }
+}
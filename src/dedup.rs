@@ -0,0 +1,114 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+
+// ============================================================================
+// Request Deduplication
+// ============================================================================
+
+/// A client request: the node it was sent to, the client and its msg_id.
+type RequestKey = (String, String, u64);
+
+/// The last `capacity` replies sent to clients, oldest evicted first.
+#[derive(Debug)]
+struct ReplyCache {
+    capacity: usize,
+    replies: HashMap<RequestKey, String>,
+    order: VecDeque<RequestKey>,
+}
+
+impl ReplyCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            replies: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, key: &RequestKey) -> Option<&String> {
+        self.replies.get(key)
+    }
+
+    /// Caches `line` under `key`, evicting the oldest replies beyond
+    /// `capacity`. Replacing a cached reply doesn't refresh its age.
+    fn insert(&mut self, key: RequestKey, line: &str) {
+        let previous = self.replies.insert(key.clone(), line.to_string());
+        if previous.is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.replies.remove(&oldest);
+            }
+        }
+    }
+}
+
+static CACHE: OnceLock<Mutex<ReplyCache>> = OnceLock::new();
+
+/// Starts remembering the last `capacity` client replies, so a retransmitted
+/// request gets its original reply again instead of being applied twice.
+pub fn enable(capacity: usize) {
+    let _ = CACHE.set(Mutex::new(ReplyCache::new(capacity)));
+}
+
+pub fn is_enabled() -> bool {
+    CACHE.get().is_some()
+}
+
+/// The reply already sent for `client`'s request `msg_id` to `node`, if it is
+/// still cached.
+pub fn cached_reply(node: &str, client: &str, msg_id: u64) -> Option<String> {
+    let cache = CACHE.get()?.lock().expect("reply cache poisoned");
+    let key = (node.to_string(), client.to_string(), msg_id);
+    cache.get(&key).cloned()
+}
+
+/// Remembers `line`, sent by `node` in reply to `client`'s request
+/// `in_reply_to`.
+pub fn record_reply(node: &str, client: &str, in_reply_to: u64, line: &str) {
+    let Some(cache) = CACHE.get() else {
+        return;
+    };
+    let mut cache = cache.lock().expect("reply cache poisoned");
+    cache.insert((node.to_string(), client.to_string(), in_reply_to), line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(msg_id: u64) -> RequestKey {
+        ("n1".to_string(), "c1".to_string(), msg_id)
+    }
+
+    #[test]
+    fn oldest_replies_are_evicted_first() {
+        let mut cache = ReplyCache::new(2);
+        cache.insert(key(1), "one");
+        cache.insert(key(2), "two");
+        cache.insert(key(3), "three");
+        assert_eq!(cache.get(&key(1)), None);
+        assert_eq!(cache.get(&key(2)).unwrap(), "two");
+        assert_eq!(cache.get(&key(3)).unwrap(), "three");
+    }
+
+    #[test]
+    fn replacing_a_reply_keeps_its_place_in_line() {
+        let mut cache = ReplyCache::new(2);
+        cache.insert(key(1), "one");
+        cache.insert(key(2), "two");
+        cache.insert(key(1), "one again");
+        assert_eq!(cache.order.len(), 2);
+        cache.insert(key(3), "three");
+        assert_eq!(cache.get(&key(1)), None);
+        assert_eq!(cache.get(&key(2)).unwrap(), "two");
+    }
+
+    #[test]
+    fn capacity_is_at_least_one() {
+        let mut cache = ReplyCache::new(0);
+        cache.insert(key(1), "one");
+        assert_eq!(cache.get(&key(1)).unwrap(), "one");
+    }
+}
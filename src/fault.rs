@@ -0,0 +1,34 @@
+use std::sync::OnceLock;
+
+// ============================================================================
+// Outbound Message Loss
+// ============================================================================
+
+/// Environment variable holding the probability (0.0..=1.0) of dropping an
+/// outbound inter-node message. Unset or unparsable disables message loss.
+pub const DROP_RATE_ENV: &str = "VORTEX_DROP_RATE";
+
+static DROP_RATE: OnceLock<f64> = OnceLock::new();
+
+fn drop_rate() -> f64 {
+    *DROP_RATE.get_or_init(|| {
+        std::env::var(DROP_RATE_ENV)
+            .ok()
+            .and_then(|value| value.parse::<f64>().ok())
+            .map(|rate| rate.clamp(0.0, 1.0))
+            .unwrap_or(0.0)
+    })
+}
+
+/// Returns true for inter-node traffic (gossip, gossip_ok, ...) as opposed to
+/// messages to or from Maelstrom clients, which are named `c<N>`.
+pub fn is_internal(dest: &str) -> bool {
+    dest.starts_with('n')
+}
+
+/// Decides whether an outbound message to `dest` should be silently dropped.
+/// Client replies are never dropped so the workload checker stays meaningful.
+pub fn should_drop(dest: &str) -> bool {
+    let rate = drop_rate();
+    rate > 0.0 && is_internal(dest) && rand::random::<f64>() < rate
+}
@@ -0,0 +1,22 @@
+use crate::cli::Workload;
+
+// ============================================================================
+// Build Fingerprint
+// ============================================================================
+
+/// One line identifying this build and how it was started, so results from
+/// different builds are never confused while tuning.
+pub fn fingerprint(workload: Option<Workload>) -> String {
+    let features = match env!("VORTEX_FEATURES") {
+        "" => "none",
+        features => features,
+    };
+    let profile = if cfg!(debug_assertions) { "debug" } else { "release" };
+    let workload = workload.map_or("all".to_string(), |w| format!("{w:?}").to_lowercase());
+
+    format!(
+        "vortex {} ({}) profile={profile} features={features} workload={workload}",
+        env!("CARGO_PKG_VERSION"),
+        env!("VORTEX_GIT_HASH"),
+    )
+}
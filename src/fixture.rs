@@ -0,0 +1,57 @@
+use std::path::Path;
+
+use serde_json::{Value, json};
+
+use crate::cli::Workload;
+
+// ============================================================================
+// Regression Fixtures
+// ============================================================================
+
+/// Turns a `--capture` file into a regression fixture: a header naming the
+/// workload, the capture's inbound lines with their offsets, and its outbound
+/// lines normalized by [`normalize`]. `tests/replay_fixtures.rs` replays
+/// each fixture under `tests/fixtures/` in virtual time and checks the node
+/// sends the same lines again.
+pub fn generate(capture: &Path, workload: Option<Workload>, out: &Path) -> anyhow::Result<()> {
+    let text = std::fs::read_to_string(capture)?;
+    let mut lines = vec![json!({ "workload": workload.map(subcommand) }).to_string()];
+    for record in text.lines() {
+        let record: Value = serde_json::from_str(record)?;
+        if record.get("in").is_some() {
+            lines.push(record.to_string());
+        } else if let Some(line) = record.get("out").and_then(Value::as_str) {
+            lines.push(json!({ "out": normalize(line)? }).to_string());
+        }
+    }
+    if let Some(dir) = out.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(out, lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// An outbound line without the parts that may differ between equivalent
+/// runs: its `msg_id`, which comes from a counter shared by every handler,
+/// and the order of a `read_ok`'s `messages`, which is a set. The fixture
+/// test applies the same rules to the replayed output.
+fn normalize(line: &str) -> anyhow::Result<String> {
+    let mut msg: Value = serde_json::from_str(line)?;
+    if let Some(body) = msg.get_mut("body").and_then(Value::as_object_mut) {
+        body.remove("msg_id");
+        if let Some(Value::Array(messages)) = body.get_mut("messages") {
+            messages.sort_by_key(|value| value.to_string());
+        }
+    }
+    Ok(msg.to_string())
+}
+
+/// The subcommand that selects `workload`.
+fn subcommand(workload: Workload) -> &'static str {
+    match workload {
+        Workload::Echo => "echo",
+        Workload::Generate => "generate",
+        Workload::Broadcast => "broadcast",
+        Workload::OrSet => "or-set",
+    }
+}
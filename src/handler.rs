@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+use anyhow::Result;
+
+use crate::cli::Workload;
+use crate::output::Output;
+use crate::{Message, Payload, challenges};
+
+// ============================================================================
+// Handler Trait
+// ============================================================================
+
+pub type HandlerFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// Handles one message type. Workloads implement this (usually through
+/// [`route!`]) and register it with a [`Registry`] under the type it serves.
+pub trait Handler: Send + Sync {
+    fn handle(&self, msg: Message<Payload>, output: Output) -> HandlerFuture;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(Message<Payload>, Output) -> HandlerFuture + Send + Sync,
+{
+    fn handle(&self, msg: Message<Payload>, output: Output) -> HandlerFuture {
+        self(msg, output)
+    }
+}
+
+/// Builds a [`Handler`] that unwraps the `$variant` payload and awaits the
+/// workload's `async fn $handler(Message<Body>, &Output) -> Result<()>`.
+macro_rules! route {
+    ($variant:path => $handler:path) => {
+        |msg: $crate::Message<$crate::Payload>,
+         output: $crate::output::Output|
+         -> $crate::handler::HandlerFuture {
+            Box::pin(async move {
+                let $crate::Message { src, dest, body } = msg;
+                match body {
+                    $variant(body) => $handler($crate::Message { src, dest, body }, &output).await,
+                    other => anyhow::bail!("{} cannot handle {other:?}", stringify!($handler)),
+                }
+            })
+        }
+    };
+}
+pub(crate) use route;
+
+// ============================================================================
+// Registry
+// ============================================================================
+
+/// Message type -> handler table consulted by the dispatcher.
+#[derive(Default)]
+pub struct Registry {
+    handlers: HashMap<&'static str, Box<dyn Handler>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry for the selected workload, or for every workload when none
+    /// is selected. `init`, the state snapshot messages and the failure
+    /// detector's probes are always registered.
+    pub fn for_workload(workload: Option<Workload>) -> Self {
+        let mut registry = Self::new();
+        challenges::init::register(&mut registry);
+        challenges::state::register(&mut registry);
+        challenges::membership::register(&mut registry);
+
+        let all = workload.is_none();
+        if all || workload == Some(Workload::Echo) {
+            challenges::echo::register(&mut registry);
+        }
+        if all || workload == Some(Workload::Generate) {
+            challenges::generate::register(&mut registry);
+        }
+        if all || workload == Some(Workload::Broadcast) {
+            challenges::broadcast::register(&mut registry);
+        }
+        // Shares the `read` type with broadcast, so only served on request.
+        if workload == Some(Workload::OrSet) {
+            challenges::crdt::register(&mut registry);
+        }
+        registry
+    }
+
+    pub fn register(&mut self, typ: &'static str, handler: impl Handler + 'static) {
+        self.handlers.insert(typ, Box::new(handler));
+    }
+
+    pub fn get(&self, typ: &str) -> Option<&dyn Handler> {
+        self.handlers.get(typ).map(|handler| handler.as_ref())
+    }
+
+    /// Every registered message type, sorted.
+    #[cfg(test)]
+    pub fn types(&self) -> Vec<&'static str> {
+        let mut types: Vec<_> = self.handlers.keys().copied().collect();
+        types.sort_unstable();
+        types
+    }
+}
@@ -0,0 +1,150 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+use crate::capture;
+use crate::channel::{self, Overflow};
+use crate::clock::{self, SimulatedTimeSource};
+
+/// Lines read ahead of the dispatch loop before the reader stops pulling from
+/// stdin, pushing back on Maelstrom instead of buffering without limit.
+const READ_AHEAD: usize = 1024;
+
+// ============================================================================
+// Stdin Reader
+// ============================================================================
+
+/// One line read from stdin and when it arrived, so the time it spent queued
+/// behind earlier messages can be measured.
+#[derive(Debug)]
+pub struct Incoming {
+    pub line: String,
+    pub received: Instant,
+}
+
+/// Spawns the task that reads the transport's input (stdin in production)
+/// line by line. The channel closes at end of input; a read error is
+/// delivered as the last item.
+pub fn spawn_reader(
+    reader: impl AsyncRead + Send + Unpin + 'static,
+) -> channel::Receiver<std::io::Result<Incoming>> {
+    let (tx, rx) = channel::channel("stdin", READ_AHEAD, Overflow::Block);
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+        loop {
+            let item = match lines.next_line().await {
+                Ok(Some(line)) => {
+                    capture::record_in(&line);
+                    Ok(Incoming {
+                        line,
+                        received: clock::now(),
+                    })
+                }
+                Ok(None) => return,
+                Err(err) => Err(err),
+            };
+            let failed = item.is_err();
+            if tx.send(item).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+// ============================================================================
+// Capture Replay
+// ============================================================================
+
+/// Feeds the inbound lines of a `--capture` file through the same channel as
+/// `spawn_reader`, each at the offset it was recorded at so timer-driven
+/// work such as gossip rounds interleaves with them as it did originally.
+/// Outbound lines are skipped; stdin is not read. A file that can't be read
+/// or parsed is delivered as an error.
+///
+/// With a simulated clock, the replayer moves it to each offset itself
+/// instead of waiting for it, so a long capture replays as fast as the
+/// handlers run while timers still fire between the same messages.
+pub fn spawn_replayer(
+    path: PathBuf,
+    virtual_clock: Option<Arc<SimulatedTimeSource>>,
+) -> channel::Receiver<std::io::Result<Incoming>> {
+    let (tx, rx) = channel::channel("stdin", READ_AHEAD, Overflow::Block);
+    tokio::spawn(async move {
+        let entries = match read_capture(&path) {
+            Ok(entries) => entries,
+            Err(err) => {
+                let _ = tx.send(Err(err)).await;
+                return;
+            }
+        };
+        let start = clock::now();
+        for (line, offset) in entries {
+            match &virtual_clock {
+                Some(source) => {
+                    source.advance_to(start + offset);
+                    // Let the timers that just came due run first.
+                    tokio::task::yield_now().await;
+                }
+                None => clock::sleep_until(start + offset).await,
+            }
+            capture::record_in(&line);
+            let item = Incoming {
+                line,
+                received: clock::now(),
+            };
+            if tx.send(Ok(item)).await.is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+/// The inbound lines of a capture file with their recorded offsets.
+fn read_capture(path: &Path) -> std::io::Result<Vec<(String, Duration)>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut entries = Vec::new();
+    for record in text.lines() {
+        let record: serde_json::Value = serde_json::from_str(record)?;
+        if let Some(line) = record.get("in").and_then(|line| line.as_str()) {
+            let ts_us = record.get("ts_us").and_then(|ts| ts.as_u64()).unwrap_or(0);
+            entries.push((line.to_string(), Duration::from_micros(ts_us)));
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn virtual_time_replay_jumps_to_each_offset() {
+        let path = std::env::temp_dir().join(format!("vortex-replay-{}.jsonl", std::process::id()));
+        let capture = [
+            r#"{"in": "first", "ts_us": 0}"#,
+            r#"{"out": "reply", "ts_us": 5000}"#,
+            r#"{"in": "second", "ts_us": 30000000}"#,
+        ];
+        std::fs::write(&path, capture.join("\n")).unwrap();
+        let source = Arc::new(SimulatedTimeSource::new(0));
+        let _guard = clock::use_on_this_thread(source.clone());
+        let start = clock::now();
+
+        let mut incoming = spawn_replayer(path.clone(), Some(source));
+        let first = incoming.recv().await.unwrap().unwrap();
+        let second = incoming.recv().await.unwrap().unwrap();
+        assert!(incoming.recv().await.is_none());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            (first.line.as_str(), second.line.as_str()),
+            ("first", "second")
+        );
+        assert_eq!(first.received - start, Duration::ZERO);
+        assert_eq!(second.received - start, Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,64 @@
+use std::io::Write;
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+
+use crate::clock;
+
+// ============================================================================
+// Structured Logging
+// ============================================================================
+
+/// Severity of a log event; enabling a level also enables every level above it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+static LEVEL: OnceLock<Level> = OnceLock::new();
+
+/// Starts writing events at `level` and above to stderr.
+pub fn enable(level: Level) {
+    let _ = LEVEL.set(level);
+}
+
+pub fn is_enabled(level: Level) -> bool {
+    LEVEL.get().is_some_and(|enabled| level <= *enabled)
+}
+
+/// Writes one JSON line with a timestamp, the level, `message` and every
+/// field of the `fields` object. The line goes out in a single write under
+/// the stderr lock, so events from concurrent tasks never interleave.
+pub fn event(level: Level, message: &str, fields: Value) {
+    if !is_enabled(level) {
+        return;
+    }
+    let ts_ms = clock::wall_clock_ms();
+
+    let mut line = Map::new();
+    line.insert("ts_ms".to_string(), ts_ms.into());
+    line.insert("level".to_string(), level.as_str().into());
+    line.insert("msg".to_string(), message.into());
+    if let Value::Object(fields) = fields {
+        line.extend(fields);
+    }
+    let mut line = Value::Object(line).to_string();
+    line.push('\n');
+    // Logging is best effort; a closed stderr must not take the node down.
+    let _ = std::io::stderr().lock().write_all(line.as_bytes());
+}
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::cli::Workload;
+use crate::{channel, fault::is_internal, fingerprint, scheduler};
+
+// ============================================================================
+// Efficiency Metrics
+// ============================================================================
+
+static SERVER_SENT: AtomicU64 = AtomicU64::new(0);
+static SERVER_RECEIVED: AtomicU64 = AtomicU64::new(0);
+static BROADCAST_OPS: AtomicU64 = AtomicU64::new(0);
+static GOSSIP_ROUNDS: AtomicU64 = AtomicU64::new(0);
+static GOSSIP_MESSAGES: AtomicU64 = AtomicU64::new(0);
+static GOSSIP_RETRIES: AtomicU64 = AtomicU64::new(0);
+static LOCK_WAIT: Histogram = Histogram::new();
+
+/// The workload the node was started for, named in every report.
+static WORKLOAD: OnceLock<Option<Workload>> = OnceLock::new();
+
+/// Messages read and written, by body type.
+#[derive(Debug, Default)]
+struct TypeCounts {
+    received: BTreeMap<String, u64>,
+    sent: BTreeMap<String, u64>,
+}
+
+static TYPE_COUNTS: OnceLock<Mutex<TypeCounts>> = OnceLock::new();
+
+fn type_counts() -> &'static Mutex<TypeCounts> {
+    TYPE_COUNTS.get_or_init(|| Mutex::new(TypeCounts::default()))
+}
+
+/// Number of power-of-two buckets; the last one also takes everything
+/// longer.
+const BUCKETS: usize = 24;
+
+/// Durations counted in power-of-two microsecond buckets: bucket `i` holds
+/// samples under `2^i` µs.
+#[derive(Debug)]
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+        }
+    }
+
+    fn record(&self, sample: Duration) {
+        let micros = sample.as_micros() as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn count(&self) -> u64 {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Upper bound in µs of the bucket holding the `quantile` sample.
+    fn quantile(&self, quantile: f64) -> u64 {
+        let rank = (self.count() as f64 * quantile).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                return 1 << i;
+            }
+        }
+        1 << (BUCKETS - 1)
+    }
+}
+
+/// Counts a message of type `typ` written to stdout, and whether it goes to
+/// another server.
+pub fn record_sent(dest: &str, typ: &str) {
+    if is_internal(dest) {
+        SERVER_SENT.fetch_add(1, Ordering::Relaxed);
+    }
+    let mut counts = type_counts().lock().expect("type counts poisoned");
+    *counts.sent.entry(typ.to_string()).or_default() += 1;
+}
+
+/// Counts a message of type `typ` read from stdin, and whether it came from
+/// another server.
+pub fn record_received(src: &str, typ: &str) {
+    if is_internal(src) {
+        SERVER_RECEIVED.fetch_add(1, Ordering::Relaxed);
+    }
+    let mut counts = type_counts().lock().expect("type counts poisoned");
+    *counts.received.entry(typ.to_string()).or_default() += 1;
+}
+
+/// Counts a gossip message resent after its acknowledgement timed out.
+pub fn record_gossip_retry() {
+    GOSSIP_RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records how long an acquisition of the cluster or a workload lock waited.
+pub fn record_lock_wait(wait: Duration) {
+    LOCK_WAIT.record(wait);
+}
+
+pub fn record_broadcast() {
+    BROADCAST_OPS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a gossip round that sent `fanout` messages; empty rounds are not
+/// counted so the average reflects rounds that had something to send.
+pub fn record_gossip_round(fanout: usize) {
+    if fanout > 0 {
+        GOSSIP_ROUNDS.fetch_add(1, Ordering::Relaxed);
+        GOSSIP_MESSAGES.fetch_add(fanout as u64, Ordering::Relaxed);
+    }
+}
+
+/// Records the workload the node serves, so reports carry the build
+/// fingerprint. Only the first call has an effect.
+pub fn set_workload(workload: Option<Workload>) {
+    let _ = WORKLOAD.set(workload);
+}
+
+/// Starts a task that logs the running totals to stderr every `interval`.
+pub fn spawn(interval: Duration) {
+    scheduler::every("metrics_timer", interval, |round| {
+        // The first round runs immediately, before anything was counted.
+        if round > 0 {
+            report();
+        }
+        async {}
+    });
+}
+
+/// Logs the build fingerprint, the running totals and queue depths to
+/// stderr.
+pub fn report() {
+    let workload = WORKLOAD.get().copied().flatten();
+    eprintln!("metrics: {}", fingerprint::fingerprint(workload));
+    let sent = SERVER_SENT.load(Ordering::Relaxed);
+    let received = SERVER_RECEIVED.load(Ordering::Relaxed);
+    let ops = BROADCAST_OPS.load(Ordering::Relaxed);
+    let rounds = GOSSIP_ROUNDS.load(Ordering::Relaxed);
+    let messages = GOSSIP_MESSAGES.load(Ordering::Relaxed);
+
+    let per_op = if ops == 0 {
+        0.0
+    } else {
+        sent as f64 / ops as f64
+    };
+    let fanout = if rounds == 0 {
+        0.0
+    } else {
+        messages as f64 / rounds as f64
+    };
+    eprintln!(
+        "metrics: {ops} broadcast ops, {sent} server msgs sent ({per_op:.2}/op), \
+         {received} received, gossip fanout {fanout:.2} over {rounds} rounds"
+    );
+    let retries = GOSSIP_RETRIES.load(Ordering::Relaxed);
+    eprintln!(
+        "metrics: {retries} gossip retries, lock wait p50 <{}us p99 <{}us over {} acquisitions",
+        LOCK_WAIT.quantile(0.5),
+        LOCK_WAIT.quantile(0.99),
+        LOCK_WAIT.count()
+    );
+    {
+        let counts = type_counts().lock().expect("type counts poisoned");
+        eprintln!(
+            "metrics: received by type {}",
+            format_counts(&counts.received)
+        );
+        eprintln!("metrics: sent by type {}", format_counts(&counts.sent));
+    }
+    // Channels sharing a name, like the one per RPC call or scheduled task,
+    // are reported together.
+    let mut queues: BTreeMap<&str, QueueTotals> = BTreeMap::new();
+    for queue in channel::all_stats() {
+        let totals = queues.entry(queue.name).or_default();
+        totals.channels += 1;
+        totals.depth += queue.depth();
+        totals.high_water = totals.high_water.max(queue.high_water());
+        totals.dropped += queue.dropped();
+    }
+    for (name, totals) in queues {
+        let channels = if totals.channels > 1 {
+            format!(" over {} channels", totals.channels)
+        } else {
+            String::new()
+        };
+        eprintln!(
+            "metrics: queue {name} depth {} (max {}), {} dropped{channels}",
+            totals.depth, totals.high_water, totals.dropped
+        );
+    }
+}
+
+#[derive(Debug, Default)]
+struct QueueTotals {
+    channels: usize,
+    depth: usize,
+    high_water: usize,
+    dropped: u64,
+}
+
+fn format_counts(counts: &BTreeMap<String, u64>) -> String {
+    counts
+        .iter()
+        .map(|(typ, count)| format!("{typ}={count}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
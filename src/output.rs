@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::{capture, clock};
+use crate::channel::{self, Overflow};
+
+// ============================================================================
+// Stdout Writer
+// ============================================================================
+
+enum Outgoing {
+    Line(String),
+    Flush(oneshot::Sender<()>),
+}
+
+impl std::fmt::Debug for Outgoing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outgoing::Line(line) => f.debug_tuple("Line").field(line).finish(),
+            Outgoing::Flush(_) => f.write_str("Flush"),
+        }
+    }
+}
+
+pub const DEFAULT_FLUSH_IDLE_MS: u64 = 5;
+
+/// Lines queued for stdout before further replies are refused. Far more than
+/// a healthy writer ever falls behind by, so hitting it means stdout has
+/// stalled.
+const STDOUT_CAPACITY: usize = 64 * 1024;
+
+/// When the writer flushes stdout. Lines are buffered until `batch_lines` are
+/// pending, or until no new line has been queued for `idle`, whichever comes
+/// first, so sparse traffic still gets replies within `idle`.
+#[derive(Debug, Clone, Copy)]
+pub struct FlushPolicy {
+    pub batch_lines: usize,
+    pub idle: Duration,
+}
+
+/// Handle to the stdout writer task. Cheap to clone; every handler and gossip
+/// task sends through one so lines never interleave.
+#[derive(Debug, Clone)]
+pub struct Output {
+    tx: channel::Sender<Outgoing>,
+}
+
+impl Output {
+    /// Queues one already-serialized message line for stdout. Fails without
+    /// waiting if the queue is full.
+    pub fn write_line(&self, line: String) -> anyhow::Result<()> {
+        self.tx
+            .try_send(Outgoing::Line(line))
+            .map_err(|err| anyhow::anyhow!("stdout writer: {err}"))
+    }
+
+    /// Waits until every line queued before this call has been written.
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        let (done_tx, done_rx) = oneshot::channel();
+        self.tx
+            .send(Outgoing::Flush(done_tx))
+            .await
+            .map_err(|err| anyhow::anyhow!("stdout writer: {err}"))?;
+        done_rx.await?;
+        Ok(())
+    }
+}
+
+/// Spawns the task that owns the transport's output (stdout in production)
+/// and writes queued lines in order, flushing according to `policy`.
+pub fn spawn_writer(
+    writer: impl AsyncWrite + Send + Unpin + 'static,
+    policy: FlushPolicy,
+) -> (Output, JoinHandle<()>) {
+    // Replies are sent from synchronous handler code, which can't wait for
+    // room: `write_line` fails when the queue is full, and only `flush` waits.
+    let (tx, rx) = channel::channel("stdout", STDOUT_CAPACITY, Overflow::Block);
+    let handle = tokio::spawn(write_loop(writer, rx, policy));
+    (Output { tx }, handle)
+}
+
+async fn write_loop(
+    mut stdout: impl AsyncWrite + Unpin,
+    mut rx: channel::Receiver<Outgoing>,
+    policy: FlushPolicy,
+) {
+    let mut pending = 0;
+    loop {
+        // Only wait for the idle timeout while there is something to flush.
+        let outgoing = if pending == 0 {
+            rx.recv().await
+        } else {
+            match clock::timeout(policy.idle, rx.recv()).await {
+                Some(outgoing) => outgoing,
+                None => {
+                    if stdout.flush().await.is_err() {
+                        return;
+                    }
+                    pending = 0;
+                    continue;
+                }
+            }
+        };
+        let Some(outgoing) = outgoing else {
+            let _ = stdout.flush().await;
+            return;
+        };
+
+        match outgoing {
+            Outgoing::Line(mut line) => {
+                capture::record_out(&line);
+                line.push('\n');
+                if stdout.write_all(line.as_bytes()).await.is_err() {
+                    return;
+                }
+                pending += 1;
+                if pending >= policy.batch_lines {
+                    if stdout.flush().await.is_err() {
+                        return;
+                    }
+                    pending = 0;
+                }
+            }
+            Outgoing::Flush(done) => {
+                if stdout.flush().await.is_err() {
+                    return;
+                }
+                pending = 0;
+                let _ = done.send(());
+            }
+        }
+    }
+}
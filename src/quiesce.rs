@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::{
+    channel, challenges::cluster::global_cluster, clock, fault::is_internal, logging, metrics,
+    rpc, scheduler,
+};
+
+// ============================================================================
+// Quiescence Detection
+// ============================================================================
+
+/// When the last client message arrived, and whether the quiet period since
+/// then has already been reported.
+#[derive(Debug)]
+struct Activity {
+    last_client: Instant,
+    reported: bool,
+}
+
+static ACTIVITY: OnceLock<Mutex<Option<Activity>>> = OnceLock::new();
+
+fn activity() -> &'static Mutex<Option<Activity>> {
+    ACTIVITY.get_or_init(|| Mutex::new(None))
+}
+
+/// Notes an incoming message; only client traffic resets the quiet period.
+pub fn record_received(src: &str) {
+    if !is_internal(src) {
+        *activity().lock().expect("quiescence state poisoned") = Some(Activity {
+            last_client: clock::now(),
+            reported: false,
+        });
+    }
+}
+
+/// Starts a task that, once no client message has arrived for `quiet_for`
+/// and every internal queue and pending RPC has drained, logs a final metrics
+/// snapshot and writes a state checkpoint to `checkpoint` if given. Reported
+/// once per quiet period.
+pub fn spawn(quiet_for: Duration, checkpoint: Option<PathBuf>) {
+    let check_every = (quiet_for / 4).max(Duration::from_millis(50));
+    scheduler::every("quiesce_timer", check_every, move |_| {
+        check(quiet_for, checkpoint.as_deref());
+        async {}
+    });
+}
+
+fn check(quiet_for: Duration, checkpoint: Option<&Path>) {
+    let quiet = {
+        let mut state = activity().lock().expect("quiescence state poisoned");
+        match state.as_mut() {
+            Some(current)
+                if !current.reported
+                    && clock::elapsed(current.last_client) >= quiet_for
+                    && is_drained() =>
+            {
+                current.reported = true;
+                Some(clock::elapsed(current.last_client))
+            }
+            _ => None,
+        }
+    };
+    let Some(quiet) = quiet else {
+        return;
+    };
+
+    logging::event(
+        logging::Level::Info,
+        "quiescence: all queues drained",
+        serde_json::json!({ "quiet_ms": quiet.as_millis() as u64 }),
+    );
+    metrics::report();
+    if let Some(path) = checkpoint {
+        let path_field = path.display().to_string();
+        match write_checkpoint(path) {
+            Ok(()) => logging::event(
+                logging::Level::Info,
+                "quiescence: checkpoint written",
+                serde_json::json!({ "path": path_field }),
+            ),
+            Err(err) => logging::event(
+                logging::Level::Error,
+                "quiescence: checkpoint failed",
+                serde_json::json!({ "path": path_field, "error": format!("{err:#}") }),
+            ),
+        }
+    }
+}
+
+fn is_drained() -> bool {
+    rpc::pending_count() == 0 && channel::all_stats().iter().all(|queue| queue.depth() == 0)
+}
+
+/// Writes each node's [`Node::snapshot`] as JSON, keyed by node id.
+///
+/// [`Node::snapshot`]: crate::challenges::node::Node::snapshot
+fn write_checkpoint(path: &Path) -> anyhow::Result<()> {
+    let snapshots: Vec<(String, Vec<u8>)> = {
+        let cluster = global_cluster().read().expect("cluster lock poisoned");
+        cluster
+            .nodes
+            .values()
+            .map(|node| (node.id.clone(), node.snapshot()))
+            .collect()
+    };
+    let mut state = BTreeMap::new();
+    for (node, snapshot) in snapshots {
+        let snapshot: serde_json::Value = serde_json::from_slice(&snapshot)?;
+        state.insert(node, snapshot);
+    }
+    std::fs::write(path, serde_json::to_vec_pretty(&state)?)?;
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, HashSet};
+
+// ============================================================================
+// Run-Compacted Value Sets
+// ============================================================================
+
+/// A set of values where runs of consecutive values can be stored as one
+/// range. Inserts go in as loose values; [`RangeSet::compact`] folds them
+/// into runs, which is what keeps the dense sets broadcast collects small.
+#[derive(Debug, Clone, Default)]
+pub struct RangeSet {
+    loose: HashSet<u64>,
+    /// First value of each run -> its last value. Runs never touch.
+    runs: BTreeMap<u64, u64>,
+}
+
+impl RangeSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, value: u64) -> bool {
+        self.loose.contains(&value) || self.run_containing(value).is_some()
+    }
+
+    pub fn insert(&mut self, value: u64) -> bool {
+        self.run_containing(value).is_none() && self.loose.insert(value)
+    }
+
+    pub fn remove(&mut self, value: u64) -> bool {
+        if self.loose.remove(&value) {
+            return true;
+        }
+        let Some((start, end)) = self.run_containing(value) else {
+            return false;
+        };
+        self.runs.remove(&start);
+        if value > start {
+            self.store(start, value - 1);
+        }
+        if value < end {
+            self.store(value + 1, end);
+        }
+        true
+    }
+
+    pub fn extend(&mut self, values: impl IntoIterator<Item = u64>) {
+        for value in values {
+            self.insert(value);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        let runs = self.runs.iter().flat_map(|(start, end)| *start..=*end);
+        self.loose.iter().copied().chain(runs)
+    }
+
+    /// Loose values and runs held: the set's size in memory, as opposed to
+    /// the number of values in it.
+    pub fn entries(&self) -> usize {
+        self.loose.len() + self.runs.len()
+    }
+
+    /// Folds loose values into runs with their neighbours. Values with no
+    /// neighbour stay loose. Returns how many entries were freed.
+    pub fn compact(&mut self) -> usize {
+        let before = self.entries();
+        let mut spans: Vec<(u64, u64)> = self.loose.drain().map(|value| (value, value)).collect();
+        spans.extend(std::mem::take(&mut self.runs));
+        spans.sort_unstable();
+
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(spans.len());
+        for (start, end) in spans {
+            match merged.last_mut() {
+                Some((_, last)) if last.checked_add(1) == Some(start) => *last = end,
+                _ => merged.push((start, end)),
+            }
+        }
+        for (start, end) in merged {
+            self.store(start, end);
+        }
+        self.loose.shrink_to_fit();
+        before - self.entries()
+    }
+
+    /// Stores `start..=end`, a single value loose.
+    fn store(&mut self, start: u64, end: u64) {
+        if start == end {
+            self.loose.insert(start);
+        } else {
+            self.runs.insert(start, end);
+        }
+    }
+
+    /// The run holding `value`, as its first and last value.
+    fn run_containing(&self, value: u64) -> Option<(u64, u64)> {
+        let (start, end) = self.runs.range(..=value).next_back()?;
+        (value <= *end).then_some((*start, *end))
+    }
+}
+
+impl FromIterator<u64> for RangeSet {
+    fn from_iter<I: IntoIterator<Item = u64>>(values: I) -> Self {
+        let mut set = Self::new();
+        set.extend(values);
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(set: &RangeSet) -> Vec<u64> {
+        let mut values: Vec<u64> = set.iter().collect();
+        values.sort_unstable();
+        values
+    }
+
+    #[test]
+    fn consecutive_values_compact_into_runs() {
+        let mut set: RangeSet = [5, 1, 2, 3, 9, 4, 7].into_iter().collect();
+        assert_eq!(set.entries(), 7);
+        assert_eq!(set.compact(), 4);
+        // 1..=5 is one run; 7 and 9 have no neighbour.
+        assert_eq!(set.entries(), 3);
+        assert_eq!(sorted(&set), vec![1, 2, 3, 4, 5, 7, 9]);
+
+        assert!(!set.insert(3));
+        assert!(set.insert(6));
+        assert_eq!(set.compact(), 2);
+        assert_eq!(sorted(&set), (1..=7).chain([9]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn removing_from_a_run_splits_it() {
+        let mut set: RangeSet = (1..=6).collect();
+        set.compact();
+        assert!(set.remove(2));
+        assert!(!set.remove(2));
+        assert!(set.remove(5));
+        // 1 and 6 are left loose, 3..=4 stays a run.
+        assert_eq!(sorted(&set), vec![1, 3, 4, 6]);
+        assert!(!set.contains(2) && set.contains(3) && set.contains(6));
+        assert!(set.remove(1) && set.remove(6));
+        assert_eq!(sorted(&set), vec![3, 4]);
+    }
+
+    #[test]
+    fn runs_reach_the_end_of_the_range() {
+        let mut set: RangeSet = [u64::MAX - 1, u64::MAX, 0].into_iter().collect();
+        set.compact();
+        assert!(set.contains(u64::MAX) && set.contains(0));
+        assert!(set.remove(u64::MAX));
+        assert_eq!(sorted(&set), vec![0, u64::MAX - 1]);
+    }
+}
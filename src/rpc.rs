@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::channel::{self, Overflow};
+use crate::{Message, Payload, clock, output::Output, send};
+
+// ============================================================================
+// Pending RPC Table
+// ============================================================================
+
+/// Completion path run when the reply to a tracked request arrives.
+pub type ReplyCallback = Box<dyn FnOnce(Message<Payload>) -> Result<()> + Send>;
+
+/// Outstanding requests keyed by (sending node, msg_id).
+static PENDING: OnceLock<Mutex<HashMap<(String, u64), ReplyCallback>>> = OnceLock::new();
+
+fn pending() -> &'static Mutex<HashMap<(String, u64), ReplyCallback>> {
+    PENDING.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Whether another thread currently holds the pending table, for stall reports.
+pub fn is_locked() -> bool {
+    matches!(
+        pending().try_lock(),
+        Err(std::sync::TryLockError::WouldBlock)
+    )
+}
+
+/// Number of requests still waiting for a reply.
+pub fn pending_count() -> usize {
+    pending().lock().expect("rpc table poisoned").len()
+}
+
+/// Records that `node_id` sent a request with `msg_id`; `callback` receives
+/// the reply instead of the regular workload handlers.
+pub fn register(node_id: &str, msg_id: u64, callback: ReplyCallback) {
+    pending()
+        .lock()
+        .expect("rpc table poisoned")
+        .insert((node_id.to_string(), msg_id), callback);
+}
+
+/// Removes and returns the completion for a reply to `node_id`, if the
+/// `in_reply_to` id belongs to a request that is still pending.
+pub fn take_pending(node_id: &str, in_reply_to: u64) -> Option<ReplyCallback> {
+    pending()
+        .lock()
+        .expect("rpc table poisoned")
+        .remove(&(node_id.to_string(), in_reply_to))
+}
+
+// ============================================================================
+// Round-Trip Times
+// ============================================================================
+
+/// Smoothed round-trip time to each node a call was answered by.
+static ROUND_TRIPS: OnceLock<Mutex<HashMap<String, Duration>>> = OnceLock::new();
+
+fn round_trips() -> &'static Mutex<HashMap<String, Duration>> {
+    ROUND_TRIPS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Folds one sample into `dest`'s estimate as a moving average weighting the
+/// new sample 1/8, as TCP does, so one slow reply doesn't reorder peers.
+fn record_round_trip(dest: &str, sample: Duration) {
+    let mut round_trips = round_trips().lock().expect("rtt table poisoned");
+    round_trips
+        .entry(dest.to_string())
+        .and_modify(|smoothed| *smoothed = (*smoothed * 7 + sample) / 8)
+        .or_insert(sample);
+}
+
+/// Smoothed round-trip time of calls to `dest`, if any has been answered.
+pub fn round_trip(dest: &str) -> Option<Duration> {
+    round_trips()
+        .lock()
+        .expect("rtt table poisoned")
+        .get(dest)
+        .copied()
+}
+
+// ============================================================================
+// Request/Response Calls
+// ============================================================================
+
+/// Sends `msg` and resolves with the reply whose `in_reply_to` matches its
+/// msg_id. Must not be awaited on the dispatch loop itself, since that loop is
+/// what delivers the reply; spawn a task around it instead.
+pub async fn call(msg: Message<Payload>, output: &Output) -> Result<Message<Payload>> {
+    let msg_id = msg
+        .body
+        .base()
+        .and_then(|base| base.msg_id)
+        .context("rpc request has no msg_id")?;
+
+    // Holds the one reply; its gauges show calls whose reply has arrived but
+    // whose caller hasn't been scheduled to pick it up yet.
+    let (reply_tx, mut reply_rx) = channel::channel("rpc_reply", 1, Overflow::Error);
+    register(
+        &msg.src,
+        msg_id,
+        Box::new(move |reply| {
+            let _ = reply_tx.try_send(reply);
+            Ok(())
+        }),
+    );
+
+    let sent = clock::now();
+    if let Err(err) = send(&msg, output) {
+        take_pending(&msg.src, msg_id);
+        return Err(err);
+    }
+    let reply = reply_rx.recv().await.context("rpc completion dropped")?;
+    record_round_trip(&msg.dest, clock::elapsed(sent));
+    Ok(reply)
+}
+
+/// Error returned by [`call_with_timeout`] when no reply arrived in time, so
+/// callers can tell a lost message apart from a local failure.
+#[derive(Debug)]
+pub struct Timeout(pub Duration);
+
+impl std::fmt::Display for Timeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rpc timed out after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// Like [`call`], but gives up after `timeout` and forgets the request so a
+/// late reply is dropped instead of completing it.
+pub async fn call_with_timeout(
+    msg: Message<Payload>,
+    timeout: Duration,
+    output: &Output,
+) -> Result<Message<Payload>> {
+    let src = msg.src.clone();
+    let msg_id = msg.body.base().and_then(|base| base.msg_id);
+
+    match clock::timeout(timeout, call(msg, output)).await {
+        Some(reply) => reply,
+        None => {
+            if let Some(msg_id) = msg_id {
+                take_pending(&src, msg_id);
+            }
+            Err(Timeout(timeout).into())
+        }
+    }
+}
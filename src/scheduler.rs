@@ -0,0 +1,127 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::channel::{self, Overflow};
+use crate::clock;
+
+// ============================================================================
+// Scheduler
+// ============================================================================
+//
+// Every timer in the node runs as a task on the tokio runtime and waits on
+// the clock's `TimeSource`, so a simulated source stops and steps all of them
+// together. Aborting the returned handle cancels the task.
+
+/// Runs `tick` every `period`, passing the round number starting at 0. The
+/// first round runs immediately. Due rounds wait behind a running one in a
+/// one-slot channel reported as `name`, so a round that overruns the period
+/// is followed by a single late round rather than a burst of catch-up rounds,
+/// and the skipped ones show up as the queue's dropped count.
+///
+/// # Panics
+///
+/// If `period` is zero. Checked here rather than left to the timer, which
+/// would panic inside the spawned task and silently stop the rounds.
+pub fn every<F, Fut>(name: &'static str, period: Duration, mut tick: F) -> JoinHandle<()>
+where
+    F: FnMut(u64) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    assert!(!period.is_zero(), "scheduler::every needs a non-zero period");
+    let (due_tx, mut due_rx) = channel::channel(name, 1, Overflow::DropOldest);
+    // Timer and rounds share one task, so aborting the handle stops both.
+    tokio::spawn(async move {
+        let timer = async move {
+            let mut next = clock::now();
+            loop {
+                clock::sleep_until(next).await;
+                if due_tx.try_send(()).is_err() {
+                    return;
+                }
+                next = (next + period).max(clock::now());
+            }
+        };
+        let rounds = async move {
+            let mut round = 0;
+            while due_rx.recv().await.is_some() {
+                tick(round).await;
+                round += 1;
+            }
+        };
+        tokio::join!(timer, rounds);
+    })
+}
+
+/// Runs `tick` over and over, passing the round number starting at 0. After
+/// each round it waits the delay that round returned, or until `wake` is
+/// notified, whichever comes first. The first round runs immediately.
+pub fn paced<F, Fut>(wake: Arc<Notify>, mut tick: F) -> JoinHandle<()>
+where
+    F: FnMut(u64) -> Fut + Send + 'static,
+    Fut: Future<Output = Duration> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut round = 0;
+        loop {
+            let delay = tick(round).await;
+            round += 1;
+            tokio::select! {
+                _ = clock::sleep(delay) => {}
+                _ = wake.notified() => {}
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "non-zero period")]
+    fn every_rejects_a_zero_period() {
+        every("test_zero", Duration::ZERO, |_| async {});
+    }
+
+    #[tokio::test]
+    async fn overrun_rounds_coalesce() {
+        let source = Arc::new(clock::SimulatedTimeSource::new(0));
+        let _guard = clock::use_on_this_thread(source.clone());
+        let (rounds_tx, mut rounds_rx) = tokio::sync::mpsc::unbounded_channel();
+        let period = Duration::from_millis(10);
+        let task = every("test_overrun", period, move |round| {
+            let rounds_tx = rounds_tx.clone();
+            async move {
+                // The first round overruns three periods.
+                if round == 0 {
+                    clock::sleep(period * 3 + period / 2).await;
+                }
+                let _ = rounds_tx.send((round, clock::now()));
+            }
+        });
+        for _ in 0..45 {
+            for _ in 0..4 {
+                tokio::task::yield_now().await;
+            }
+            source.advance(Duration::from_millis(1));
+        }
+        let (_, first_done) = rounds_rx.recv().await.unwrap();
+        let (second, second_at) = rounds_rx.recv().await.unwrap();
+        let (third, third_at) = rounds_rx.recv().await.unwrap();
+        let timer = channel::all_stats()
+            .into_iter()
+            .find(|queue| queue.name == "test_overrun")
+            .unwrap();
+        task.abort();
+
+        // One late round right away, then back to the period.
+        assert_eq!((second, third), (1, 2));
+        assert_eq!(second_at, first_done);
+        assert!(third_at - second_at <= period);
+        assert_eq!(timer.dropped(), 2);
+    }
+}
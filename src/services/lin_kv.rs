@@ -0,0 +1,294 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{
+    BodyBase, ErrorCode, Message, Payload,
+    challenges::{broadcast::ReadBody, cluster::global_cluster, node::MsgIdSpace},
+    output::Output,
+    rpc,
+};
+
+// ============================================================================
+// Message Body Types
+// ============================================================================
+
+/// Body of the `write`/`cas` requests understood by Maelstrom's KV services.
+/// Reads use [`ReadBody`], since `read` is shared with the broadcast workload.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KvBody {
+    #[serde(flatten)]
+    pub base: BodyBase,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub create_if_not_exists: Option<bool>,
+}
+
+// ============================================================================
+// lin-kv Client
+// ============================================================================
+
+/// Node id of Maelstrom's linearizable key-value service.
+pub const SERVICE: &str = "lin-kv";
+
+const REQUEST_TIMEOUT_MS: u64 = 1000;
+
+/// Client for Maelstrom's `lin-kv` service, sending as `node_id`.
+#[derive(Debug, Clone)]
+pub struct LinKv {
+    node_id: String,
+    output: Output,
+}
+
+impl LinKv {
+    pub fn new(node_id: impl Into<String>, output: Output) -> Self {
+        Self {
+            node_id: node_id.into(),
+            output,
+        }
+    }
+
+    /// Reads `key`, returning `None` if it has never been written.
+    pub async fn read<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let body = Payload::Read(ReadBody {
+            base: self.request_base()?,
+            key: Some(Value::from(key)),
+            ..Default::default()
+        });
+
+        match self.call(body).await? {
+            Payload::ReadOk(body) => {
+                let value = body.value.context("read_ok without value")?;
+                Ok(Some(serde_json::from_value(value)?))
+            }
+            Payload::Error(err) if err.is(ErrorCode::KeyDoesNotExist) => Ok(None),
+            other => bail!("unexpected lin-kv read reply: {other:?}"),
+        }
+    }
+
+    pub async fn write<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        let body = Payload::Write(KvBody {
+            base: self.request_base()?,
+            key: Some(Value::from(key)),
+            value: Some(serde_json::to_value(value)?),
+            ..Default::default()
+        });
+
+        match self.call(body).await? {
+            Payload::WriteOk(_) => Ok(()),
+            other => bail!("unexpected lin-kv write reply: {other:?}"),
+        }
+    }
+
+    /// Compare-and-set `key` from `from` to `to`. Returns `false` if the
+    /// current value is not `from` (or the key is missing and
+    /// `create_if_not_exists` is off).
+    pub async fn cas<T: Serialize>(
+        &self,
+        key: &str,
+        from: &T,
+        to: &T,
+        create_if_not_exists: bool,
+    ) -> Result<bool> {
+        let body = Payload::Cas(KvBody {
+            base: self.request_base()?,
+            key: Some(Value::from(key)),
+            from: Some(serde_json::to_value(from)?),
+            to: Some(serde_json::to_value(to)?),
+            create_if_not_exists: Some(create_if_not_exists),
+            ..Default::default()
+        });
+
+        match self.call(body).await? {
+            Payload::CasOk(_) => Ok(true),
+            Payload::Error(err)
+                if err.is(ErrorCode::PreconditionFailed) || err.is(ErrorCode::KeyDoesNotExist) =>
+            {
+                Ok(false)
+            }
+            other => bail!("unexpected lin-kv cas reply: {other:?}"),
+        }
+    }
+
+    /// Read-modify-write of `key`: applies `update` to the current value
+    /// (`None` if missing) and retries from a fresh read whenever another
+    /// writer wins the CAS. Returns the value that was stored.
+    pub async fn cas_loop<T, F>(&self, key: &str, update: F) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: Fn(Option<&T>) -> T,
+    {
+        loop {
+            let current: Option<T> = self.read(key).await?;
+            let next = update(current.as_ref());
+            let stored = match &current {
+                Some(current) => self.cas(key, current, &next, false).await?,
+                // A missing key can't be CAS'd from a value, so create it; a
+                // concurrent creator makes this fail and we retry.
+                None => self.cas(key, &Value::Null, &serde_json::to_value(&next)?, true).await?,
+            };
+            if stored {
+                return Ok(next);
+            }
+        }
+    }
+
+    fn request_base(&self) -> Result<BodyBase> {
+        let cluster = global_cluster().read().expect("cluster lock poisoned");
+        let node = cluster
+            .get_node(&self.node_id)
+            .context("node not found in cluster")?;
+        Ok(BodyBase {
+            msg_id: Some(node.get_next_id(MsgIdSpace::Service)),
+            in_reply_to: None,
+        })
+    }
+
+    async fn call(&self, body: Payload) -> Result<Payload> {
+        let request = Message {
+            src: self.node_id.clone(),
+            dest: SERVICE.to_string(),
+            body,
+        };
+        let timeout = Duration::from_millis(REQUEST_TIMEOUT_MS);
+        let reply = rpc::call_with_timeout(request, timeout, &self.output).await?;
+        Ok(reply.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+
+    use super::*;
+    use crate::challenges::node::Node;
+    use crate::output::{FlushPolicy, spawn_writer};
+    use crate::{ErrorBody, rpc};
+
+    /// Requests the client under test sent to lin-kv, read off its output.
+    struct StubService {
+        requests: Lines<BufReader<tokio::io::DuplexStream>>,
+    }
+
+    impl StubService {
+        /// Registers `node_id` in the cluster and returns a client sending as
+        /// it, with the stub standing in for the service.
+        fn start(node_id: &str) -> (LinKv, StubService) {
+            let node = Node::new(node_id.to_string(), vec![node_id.to_string()]);
+            global_cluster()
+                .write()
+                .expect("cluster lock poisoned")
+                .add_node(node);
+            let (writer, reader) = tokio::io::duplex(64 * 1024);
+            let policy = FlushPolicy {
+                batch_lines: 1,
+                idle: Duration::from_millis(1),
+            };
+            let (output, _writer) = spawn_writer(writer, policy);
+            let stub = StubService {
+                requests: BufReader::new(reader).lines(),
+            };
+            (LinKv::new(node_id, output), stub)
+        }
+
+        async fn next_request(&mut self) -> Message<Value> {
+            let line = self.requests.next_line().await.unwrap().unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+
+        /// Completes `request` with `reply`, as the dispatch loop would.
+        fn reply(&self, request: &Message<Value>, reply: Payload) {
+            let msg_id = request.body["msg_id"].as_u64().unwrap();
+            let callback = rpc::take_pending(&request.src, msg_id).unwrap();
+            let reply = Message {
+                src: SERVICE.to_string(),
+                dest: request.src.clone(),
+                body: reply,
+            };
+            callback(reply).unwrap();
+        }
+    }
+
+    fn read_ok(value: Value) -> Payload {
+        Payload::ReadOk(ReadBody {
+            value: Some(value),
+            ..Default::default()
+        })
+    }
+
+    fn error(code: ErrorCode) -> Payload {
+        Payload::Error(ErrorBody::new(code, "stub", None))
+    }
+
+    #[tokio::test]
+    async fn cas_loop_retries_when_another_writer_wins() {
+        let (kv, mut service) = StubService::start("kv-retry");
+        let update = tokio::spawn(async move {
+            kv.cas_loop("counter", |current: Option<&u64>| current.copied().unwrap_or(0) + 1)
+                .await
+        });
+
+        let read = service.next_request().await;
+        assert_eq!(read.body["type"], "read");
+        service.reply(&read, read_ok(json!(1)));
+        let cas = service.next_request().await;
+        assert_eq!((cas.body["from"].clone(), cas.body["to"].clone()), (json!(1), json!(2)));
+        service.reply(&cas, error(ErrorCode::PreconditionFailed));
+
+        let read = service.next_request().await;
+        service.reply(&read, read_ok(json!(5)));
+        let cas = service.next_request().await;
+        assert_eq!((cas.body["from"].clone(), cas.body["to"].clone()), (json!(5), json!(6)));
+        service.reply(&cas, Payload::CasOk(KvBody::default()));
+
+        assert_eq!(update.await.unwrap().unwrap(), 6);
+    }
+
+    #[tokio::test]
+    async fn cas_loop_creates_a_missing_key() {
+        let (kv, mut service) = StubService::start("kv-create");
+        let update = tokio::spawn(async move {
+            kv.cas_loop("counter", |current: Option<&u64>| current.copied().unwrap_or(0) + 1)
+                .await
+        });
+
+        let read = service.next_request().await;
+        service.reply(&read, error(ErrorCode::KeyDoesNotExist));
+        let cas = service.next_request().await;
+        assert_eq!(cas.body["type"], "cas");
+        assert_eq!(cas.body["to"], json!(1));
+        assert_eq!(cas.body["create_if_not_exists"], json!(true));
+        service.reply(&cas, Payload::CasOk(KvBody::default()));
+
+        assert_eq!(update.await.unwrap().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn unexpected_reply_is_an_error() {
+        let (kv, mut service) = StubService::start("kv-unexpected");
+        let write = tokio::spawn(async move { kv.write("key", &1).await });
+
+        let request = service.next_request().await;
+        assert_eq!(request.body["type"], "write");
+        service.reply(&request, error(ErrorCode::Crash));
+
+        assert!(write.await.unwrap().is_err());
+    }
+}
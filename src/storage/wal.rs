@@ -0,0 +1,221 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+// ============================================================================
+// Write-Ahead Log
+// ============================================================================
+
+/// Length and CRC-32 of the payload, both little-endian u32s.
+const FRAME_HEADER: usize = 8;
+
+/// An append-only log of records. Each record is framed with its length and
+/// checksum and synced to disk before `append` returns. A checkpoint replaces
+/// everything logged up to some point with one record summarising it.
+#[derive(Debug)]
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+    /// Bytes written since the log was opened or last checkpointed.
+    since_checkpoint: u64,
+}
+
+impl Wal {
+    /// Opens the log at `path`, creating it and its directory if needed, and
+    /// returns every intact record in order. A torn record at the tail, left
+    /// by a crash during an append, is cut off along with everything after it.
+    pub fn open(path: &Path) -> Result<(Wal, Vec<Vec<u8>>)> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while let Some((payload, next)) = read_frame(&bytes, offset) {
+            records.push(payload.to_vec());
+            offset = next;
+        }
+        if offset < bytes.len() {
+            file.set_len(offset as u64)?;
+            file.sync_all()?;
+        }
+        let wal = Wal {
+            path: path.to_path_buf(),
+            file,
+            since_checkpoint: offset as u64,
+        };
+        Ok((wal, records))
+    }
+
+    /// Appends `record` and waits until it is on disk.
+    pub fn append(&mut self, record: &[u8]) -> Result<()> {
+        let frame = frame(record);
+        self.file.write_all(&frame)?;
+        self.file.sync_data()?;
+        self.since_checkpoint += frame.len() as u64;
+        Ok(())
+    }
+
+    /// Size of the log in bytes; a position to pass to `checkpoint`.
+    pub fn len(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Bytes written since the log was opened or last checkpointed.
+    pub fn since_checkpoint(&self) -> u64 {
+        self.since_checkpoint
+    }
+
+    /// Replaces the first `covered` bytes of the log with `snapshot`, a
+    /// record that stands for every record in them, keeping the records
+    /// appended after that point. The new log is written beside the old one
+    /// and renamed over it, so a crash leaves one or the other intact.
+    pub fn checkpoint(&mut self, snapshot: &[u8], covered: u64) -> Result<()> {
+        let bytes = fs::read(&self.path)?;
+        let tail = bytes.get(covered as usize..).unwrap_or_default();
+        let mut rewritten = frame(snapshot);
+        rewritten.extend_from_slice(tail);
+
+        let staging = self.path.with_extension("wal.tmp");
+        let mut file = File::create(&staging)?;
+        file.write_all(&rewritten)?;
+        file.sync_all()?;
+        fs::rename(&staging, &self.path)
+            .with_context(|| format!("replacing {}", self.path.display()))?;
+        // The rename itself is only durable once the directory is synced.
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            File::open(dir)?.sync_all()?;
+        }
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.since_checkpoint = tail.len() as u64;
+        Ok(())
+    }
+}
+
+/// `payload` framed with its length and checksum.
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32(payload).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// The payload of the frame at `offset` and the offset of the next frame, or
+/// `None` if the frame is incomplete or fails its checksum.
+fn read_frame(bytes: &[u8], offset: usize) -> Option<(&[u8], usize)> {
+    let header = bytes.get(offset..offset + FRAME_HEADER)?;
+    let len = u32::from_le_bytes(header[..4].try_into().ok()?) as usize;
+    let crc = u32::from_le_bytes(header[4..].try_into().ok()?);
+    let start = offset + FRAME_HEADER;
+    let payload = bytes.get(start..start + len)?;
+    (crc32(payload) == crc).then_some((payload, start + len))
+}
+
+/// CRC-32 (IEEE), computed bit by bit. Records are small and every append
+/// ends in an fsync, so a lookup table wouldn't be noticed.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vortex-wal-{}", std::process::id()));
+        let path = dir.join(format!("{name}.wal"));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn write_log(name: &str, records: &[&[u8]]) -> PathBuf {
+        let path = log_path(name);
+        let (mut wal, _) = Wal::open(&path).unwrap();
+        for record in records {
+            wal.append(record).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn records_survive_a_reopen() {
+        let path = write_log("reopen", &[b"[1]", b"[2,3]"]);
+        let (_, records) = Wal::open(&path).unwrap();
+        assert_eq!(records, [b"[1]".to_vec(), b"[2,3]".to_vec()]);
+    }
+
+    #[test]
+    fn a_torn_tail_is_cut_off() {
+        let path = write_log("torn", &[b"[1]", b"[2]"]);
+        let intact = fs::metadata(&path).unwrap().len();
+        // A crash partway through appending a third record.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&frame(b"[3]")[..6]).unwrap();
+        drop(file);
+
+        let (mut wal, records) = Wal::open(&path).unwrap();
+        assert_eq!(records, [b"[1]".to_vec(), b"[2]".to_vec()]);
+        assert_eq!(wal.len().unwrap(), intact);
+        wal.append(b"[4]").unwrap();
+        let (_, records) = Wal::open(&path).unwrap();
+        assert_eq!(records.last().unwrap(), b"[4]");
+    }
+
+    #[test]
+    fn a_record_failing_its_checksum_ends_the_log() {
+        let path = write_log("crc", &[b"[1]", b"[22]", b"[3]"]);
+        let mut bytes = fs::read(&path).unwrap();
+        // Corrupt the second record's payload.
+        let second = frame(b"[1]").len() + FRAME_HEADER + 1;
+        bytes[second] = b'7';
+        fs::write(&path, &bytes).unwrap();
+
+        let (_, records) = Wal::open(&path).unwrap();
+        assert_eq!(records, [b"[1]".to_vec()]);
+        let intact = frame(b"[1]").len() as u64;
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+    }
+
+    #[test]
+    fn crc32_matches_the_ieee_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn checkpoint_keeps_what_was_appended_after_the_snapshot() {
+        let path = log_path("checkpoint");
+        let (mut wal, _) = Wal::open(&path).unwrap();
+        wal.append(b"[1]").unwrap();
+        wal.append(b"[2]").unwrap();
+        let covered = wal.len().unwrap();
+        wal.append(b"[3]").unwrap();
+
+        wal.checkpoint(br#"{"broadcast":[1,2]}"#, covered).unwrap();
+        assert_eq!(wal.since_checkpoint(), frame(b"[3]").len() as u64);
+        wal.append(b"[4]").unwrap();
+        drop(wal);
+
+        let (_, records) = Wal::open(&path).unwrap();
+        let records: Vec<&[u8]> = records.iter().map(Vec::as_slice).collect();
+        assert_eq!(records, [&br#"{"broadcast":[1,2]}"#[..], b"[3]", b"[4]"]);
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use serde::Serialize;
+
+use crate::Message;
+use crate::challenges::node::MsgIdSpace;
+
+// ============================================================================
+// Strict Mode
+// ============================================================================
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STATE: OnceLock<Mutex<StrictState>> = OnceLock::new();
+
+/// Values observed so far, used to detect regressions between messages.
+#[derive(Debug, Default)]
+struct StrictState {
+    /// Hash of the message last sent under each (src, msg_id).
+    sent_msg_ids: HashMap<(String, u64), u64>,
+    /// Last msg_id each node drew from each space.
+    allocated: HashMap<(String, MsgIdSpace), u64>,
+    high_water: HashMap<(String, &'static str), u64>,
+}
+
+fn state() -> &'static Mutex<StrictState> {
+    STATE.get_or_init(|| Mutex::new(StrictState::default()))
+}
+
+/// Turns on protocol assertions for the rest of the process.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Checks the invariants every outgoing message must satisfy.
+pub fn check_outgoing<T: Serialize>(msg: &Message<T>) {
+    if msg.src == msg.dest {
+        violation(format!("message sent to self: {}", describe(msg)));
+    }
+
+    let body = serde_json::to_value(&msg.body).unwrap_or_default();
+    let typ = body
+        .get("type")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let is_reply = typ.ends_with("_ok") || typ == "error";
+    if is_reply && body.get("in_reply_to").is_none() {
+        violation(format!("reply without in_reply_to: {}", describe(msg)));
+    }
+
+    // Gossip and RPC tasks send concurrently with handlers, so ids can go out
+    // of order; `allocate_msg_id` checks their order where they are handed
+    // out. Retransmissions deliberately repeat an id, so here only reusing an
+    // id for a different message is a violation.
+    if let Some(msg_id) = body.get("msg_id").and_then(|v| v.as_u64()) {
+        let mut hasher = DefaultHasher::new();
+        describe(msg).hash(&mut hasher);
+        let fingerprint = hasher.finish();
+
+        let mut state = state().lock().expect("strict state poisoned");
+        let previous = state
+            .sent_msg_ids
+            .insert((msg.src.clone(), msg_id), fingerprint);
+        if previous.is_some_and(|previous| previous != fingerprint) {
+            violation(format!("msg_id {msg_id} reused: {}", describe(msg)));
+        }
+    }
+}
+
+/// Allocates a msg_id from `node_id`'s `space` with `allocate` and asserts it
+/// is above every id the node drew from that space before and inside the
+/// space's band. The allocation runs under the strict-mode lock, so ids are
+/// checked in the order they were handed out.
+pub fn allocate_msg_id(node_id: &str, space: MsgIdSpace, allocate: impl FnOnce() -> u64) -> u64 {
+    let mut state = state().lock().expect("strict state poisoned");
+    let msg_id = allocate();
+    if let Some(diagnostic) = check_allocation(&mut state.allocated, node_id, space, msg_id) {
+        drop(state);
+        violation(diagnostic);
+    }
+    msg_id
+}
+
+fn check_allocation(
+    allocated: &mut HashMap<(String, MsgIdSpace), u64>,
+    node_id: &str,
+    space: MsgIdSpace,
+    msg_id: u64,
+) -> Option<String> {
+    if !space.band().contains(&msg_id) {
+        return Some(format!(
+            "{space:?} msg_id {msg_id} on {node_id} is outside its band"
+        ));
+    }
+    match allocated.insert((node_id.to_string(), space), msg_id) {
+        Some(previous) if msg_id <= previous => Some(format!(
+            "{space:?} msg_id on {node_id} went from {previous} to {msg_id}"
+        )),
+        _ => None,
+    }
+}
+
+/// Asserts that `value` never decreases for the given node and metric,
+/// e.g. the size of the broadcast set or a committed offset.
+pub fn check_monotonic(node_id: &str, what: &'static str, value: u64) {
+    let mut state = state().lock().expect("strict state poisoned");
+    let previous = state
+        .high_water
+        .entry((node_id.to_string(), what))
+        .or_insert(value);
+    if value < *previous {
+        violation(format!(
+            "{what} on {node_id} regressed from {} to {value}",
+            *previous
+        ));
+    }
+    *previous = value;
+}
+
+fn describe<T: Serialize>(msg: &Message<T>) -> String {
+    serde_json::to_string(msg).unwrap_or_else(|_| "<unserializable>".to_string())
+}
+
+fn violation(diagnostic: String) -> ! {
+    eprintln!("strict mode violation: {diagnostic}");
+    std::process::abort()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocation_must_increase_within_a_space() {
+        let mut allocated = HashMap::new();
+        let mut check = |node, space, msg_id| check_allocation(&mut allocated, node, space, msg_id);
+        let gossip = MsgIdSpace::Gossip.band().start;
+        assert!(check("n1", MsgIdSpace::Client, 0).is_none());
+        assert!(check("n1", MsgIdSpace::Client, 1).is_none());
+        assert!(check("n1", MsgIdSpace::Gossip, gossip).is_none());
+        // Other nodes count on their own.
+        assert!(check("n2", MsgIdSpace::Client, 0).is_none());
+        assert!(check("n1", MsgIdSpace::Client, 1).is_some());
+        assert!(check("n1", MsgIdSpace::Client, 0).is_some());
+    }
+
+    #[test]
+    fn allocation_must_stay_in_its_band() {
+        let mut allocated = HashMap::new();
+        let past_end = MsgIdSpace::Client.band().end;
+        assert!(check_allocation(&mut allocated, "n1", MsgIdSpace::Client, past_end).is_some());
+    }
+}
@@ -0,0 +1,54 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::clock;
+
+// ============================================================================
+// Per-Message Timing
+// ============================================================================
+
+/// Where the time handling one message (or one gossip round) went, beyond
+/// its total duration.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    /// Waiting to acquire the cluster and workload locks.
+    pub lock_wait: Duration,
+    /// Serializing outgoing messages.
+    pub serialize: Duration,
+}
+
+tokio::task_local! {
+    static CURRENT: Cell<Timings>;
+}
+
+/// Runs `future`, returning its output and the lock wait and serialization
+/// time recorded while it ran. Work on other tasks is not counted.
+pub async fn measure<F: Future>(future: F) -> (F::Output, Timings) {
+    CURRENT
+        .scope(Cell::new(Timings::default()), async {
+            let output = future.await;
+            (output, CURRENT.with(Cell::get))
+        })
+        .await
+}
+
+/// Charges time spent waiting for a lock since `started` to the
+/// enclosing [`measure`], if any.
+pub fn record_lock_wait(started: Instant) {
+    record(|timings| timings.lock_wait += clock::elapsed(started));
+}
+
+/// Charges serialization time since `started` to the enclosing [`measure`],
+/// if any.
+pub fn record_serialize(started: Instant) {
+    record(|timings| timings.serialize += clock::elapsed(started));
+}
+
+fn record(update: impl FnOnce(&mut Timings)) {
+    let _ = CURRENT.try_with(|current| {
+        let mut timings = current.get();
+        update(&mut timings);
+        current.set(timings);
+    });
+}
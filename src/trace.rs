@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use serde_json::{Value, json};
+
+use crate::channel::{self, Overflow};
+use crate::clock;
+
+// ============================================================================
+// Chrome Trace Export
+// ============================================================================
+
+/// Track ("thread") ids the events are grouped under in the trace viewer.
+pub const DISPATCH_TRACK: u64 = 1;
+pub const GOSSIP_TRACK: u64 = 2;
+
+/// Events waiting for the writer task. Tracing must never hold up a handler,
+/// so when the file can't keep up the oldest events are discarded.
+const BACKLOG: usize = 4096;
+
+static SINK: OnceLock<channel::Sender<Value>> = OnceLock::new();
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Starts writing trace events to `path` in the Chrome JSON array format.
+/// Each event is written as it happens and the closing `]` is left off
+/// (the format allows it), so the file stays loadable if the node is killed.
+pub fn enable(path: &Path) -> anyhow::Result<()> {
+    let mut file = LineWriter::new(File::create(path)?);
+    file.write_all(b"[\n")?;
+    START.get_or_init(clock::now);
+
+    let (tx, mut rx) = channel::channel("trace", BACKLOG, Overflow::DropOldest);
+    SINK.set(tx)
+        .map_err(|_| anyhow::anyhow!("tracing already enabled"))?;
+    // A plain thread rather than a blocking task, which would keep the
+    // runtime from shutting down at end of input.
+    let handle = tokio::runtime::Handle::current();
+    std::thread::spawn(move || {
+        // Tracing is best effort; a failed write must not take the node down.
+        while let Some(event) = handle.block_on(rx.recv()) {
+            if writeln!(file, "{event},").is_err() {
+                return;
+            }
+        }
+    });
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    SINK.get().is_some()
+}
+
+/// Records a complete span from `start` to `end`.
+pub fn span(name: &str, cat: &str, track: u64, start: Instant, end: Instant, args: Value) {
+    write_event(json!({
+        "name": name,
+        "cat": cat,
+        "ph": "X",
+        "ts": micros_since_start(start),
+        "dur": end.saturating_duration_since(start).as_micros() as u64,
+        "pid": 1,
+        "tid": track,
+        "args": args,
+    }));
+}
+
+/// Records a zero-length event at the current time.
+pub fn instant(name: &str, cat: &str, track: u64, args: Value) {
+    write_event(json!({
+        "name": name,
+        "cat": cat,
+        "ph": "i",
+        "s": "t",
+        "ts": micros_since_start(clock::now()),
+        "pid": 1,
+        "tid": track,
+        "args": args,
+    }));
+}
+
+fn micros_since_start(at: Instant) -> u64 {
+    let start = *START.get_or_init(clock::now);
+    at.saturating_duration_since(start).as_micros() as u64
+}
+
+fn write_event(event: Value) {
+    if let Some(sink) = SINK.get() {
+        let _ = sink.try_send(event);
+    }
+}
@@ -0,0 +1,63 @@
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+
+// ============================================================================
+// Transports
+// ============================================================================
+
+pub type BoxReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type BoxWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Where a node reads its newline-delimited input messages and writes its
+/// output messages. Handlers never see it: the input reader and the output
+/// writer task own its two halves.
+pub trait Transport {
+    fn into_parts(self) -> (BoxReader, BoxWriter);
+}
+
+/// stdin and stdout, as Maelstrom runs a node.
+#[derive(Debug, Default)]
+pub struct StdioTransport;
+
+impl Transport for StdioTransport {
+    fn into_parts(self) -> (BoxReader, BoxWriter) {
+        (Box::new(tokio::io::stdin()), Box::new(tokio::io::stdout()))
+    }
+}
+
+/// In-memory pipes, for driving a node in-process: whatever is written to
+/// the peer's `input` is read by the node, and the node's output can be read
+/// from the peer's `output`.
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug)]
+pub struct ChannelTransport {
+    input: DuplexStream,
+    output: DuplexStream,
+}
+
+/// The driving side of a [`ChannelTransport`].
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug)]
+pub struct ChannelPeer {
+    pub input: DuplexStream,
+    pub output: DuplexStream,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl ChannelTransport {
+    /// A transport and its peer, each pipe buffering up to `capacity` bytes.
+    pub fn new(capacity: usize) -> (Self, ChannelPeer) {
+        let (input, peer_input) = tokio::io::duplex(capacity);
+        let (output, peer_output) = tokio::io::duplex(capacity);
+        let peer = ChannelPeer {
+            input: peer_input,
+            output: peer_output,
+        };
+        (Self { input, output }, peer)
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn into_parts(self) -> (BoxReader, BoxWriter) {
+        (Box::new(self.input), Box::new(self.output))
+    }
+}
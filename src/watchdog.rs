@@ -0,0 +1,86 @@
+use std::sync::{Mutex, OnceLock, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{challenges::cluster::global_cluster, clock, logging, rpc};
+
+// ============================================================================
+// Stall Watchdog
+// ============================================================================
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The message currently being dispatched.
+#[derive(Debug)]
+struct InFlight {
+    description: String,
+    started: Instant,
+    reported: bool,
+}
+
+static IN_FLIGHT: OnceLock<Mutex<Option<InFlight>>> = OnceLock::new();
+
+fn in_flight() -> &'static Mutex<Option<InFlight>> {
+    IN_FLIGHT.get_or_init(|| Mutex::new(None))
+}
+
+/// Marks the start of dispatching a message, described for the stall report.
+pub fn begin(description: String) {
+    *in_flight().lock().expect("watchdog state poisoned") = Some(InFlight {
+        description,
+        started: clock::now(),
+        reported: false,
+    });
+}
+
+pub fn end() {
+    *in_flight().lock().expect("watchdog state poisoned") = None;
+}
+
+/// Starts an OS thread (not a tokio task, so it keeps running when every
+/// runtime worker is blocked) that logs a warning when a single message has
+/// been in dispatch for longer than `stall_after`.
+pub fn spawn(stall_after: Duration) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(CHECK_INTERVAL);
+
+            let mut state = in_flight().lock().expect("watchdog state poisoned");
+            let Some(current) = state.as_mut() else {
+                continue;
+            };
+            let elapsed = clock::elapsed(current.started);
+            if current.reported || elapsed < stall_after {
+                continue;
+            }
+            current.reported = true;
+            let description = current.description.clone();
+            drop(state);
+
+            report(&description, elapsed);
+        }
+    });
+}
+
+/// Dumps what the stalled dispatch is waiting on. Other threads' stacks can't
+/// be captured from here, so the report probes the shared locks instead;
+/// attach a debugger (`gdb -p <pid>`, `thread apply all bt`) for full stacks.
+fn report(description: &str, elapsed: Duration) {
+    let cluster = match global_cluster().try_write() {
+        Ok(_) => "free",
+        Err(TryLockError::WouldBlock) => "HELD",
+        Err(TryLockError::Poisoned(_)) => "poisoned",
+    };
+    let rpc_table = if rpc::is_locked() { "HELD" } else { "free" };
+
+    logging::event(
+        logging::Level::Warn,
+        "watchdog: dispatch stalled",
+        serde_json::json!({
+            "stalled_ms": elapsed.as_millis() as u64,
+            "message": description,
+            "cluster_lock": cluster,
+            "rpc_table_lock": rpc_table,
+        }),
+    );
+}
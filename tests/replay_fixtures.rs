@@ -0,0 +1,76 @@
+//! Replays every fixture under `tests/fixtures/` through the node binary in
+//! virtual time and checks it sends the lines recorded in the fixture.
+//! `vortex fixture <CAPTURE> tests/fixtures/<name>.jsonl [WORKLOAD]` turns a
+//! `--capture` file into a new fixture.
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use serde_json::Value;
+
+/// Mirrors `fixture::normalize`: drops the `msg_id` and sorts a `messages` set.
+fn normalize(line: &str) -> String {
+    let mut msg: Value = serde_json::from_str(line).expect("node wrote a line that isn't JSON");
+    if let Some(body) = msg.get_mut("body").and_then(Value::as_object_mut) {
+        body.remove("msg_id");
+        if let Some(Value::Array(messages)) = body.get_mut("messages") {
+            messages.sort_by_key(|value| value.to_string());
+        }
+    }
+    msg.to_string()
+}
+
+fn replay(fixture: &Path) {
+    let text = std::fs::read_to_string(fixture).unwrap();
+    let mut records = text
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap());
+    let header = records.next().expect("fixture has no header");
+    let expected: Vec<String> = records
+        .filter_map(|record| {
+            record
+                .get("out")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        })
+        .collect();
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_vortex"));
+    command.args(["replay", "--virtual-time"]).arg(fixture);
+    if let Some(workload) = header["workload"].as_str() {
+        command.arg(workload);
+    }
+    let output = command
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{} failed: {}",
+        fixture.display(),
+        output.status
+    );
+    let actual: Vec<String> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(normalize)
+        .collect();
+
+    assert_eq!(actual, expected, "{} diverged", fixture.display());
+}
+
+#[test]
+fn fixtures_replay_to_the_recorded_output() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut fixtures: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+        .collect();
+    fixtures.sort();
+    assert!(!fixtures.is_empty(), "no fixtures under tests/fixtures");
+    for fixture in fixtures {
+        replay(&fixture);
+    }
+}